@@ -18,12 +18,11 @@ fn main() {
     may::config().set_io_workers(1);
     env_logger::init();
     // config the timeout would hurt the performance here
-    let mut server = HttpServer::new(hello);
-    server
-        .set_server_name("may_http".to_owned())
-        .set_read_timeout(Some(Duration::from_secs(10)))
-        .set_write_timeout(Some(Duration::from_secs(10)));
-
-    let server = server.start("127.0.0.1:8080").unwrap();
+    let server = HttpServer::builder(hello)
+        .server_name("may_http")
+        .read_timeout(Some(Duration::from_secs(10)))
+        .write_timeout(Some(Duration::from_secs(10)))
+        .start("127.0.0.1:8080")
+        .unwrap();
     server.wait();
 }
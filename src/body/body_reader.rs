@@ -1,13 +1,32 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::io::{self, Read};
 use std::rc::Rc;
 
 use self::BodyReader::*;
 
+/// the default max length of a chunk size line or a trailer line
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 4096;
+
+// the max line length of the chunked bodies read in the coroutine,
+// it's set by the server for each connection
+coroutine_local!(static MAX_LINE_LENGTH: Cell<usize> = Cell::new(DEFAULT_MAX_LINE_LENGTH));
+
+// set the max length of a chunk size line or a trailer line for the chunked
+// bodies that are read in the current coroutine
+pub(crate) fn set_max_line_length(max: usize) {
+    MAX_LINE_LENGTH.with(|m| m.set(max));
+}
+
+fn max_line_length() -> usize {
+    MAX_LINE_LENGTH.with(|m| m.get())
+}
+
 pub enum BodyReader {
     SizedReader(Rc<RefCell<Read>>, usize),
     ChunkReader(Rc<RefCell<Read>>, Option<usize>),
+    // the body ends when the connection is closed, set once the eof is read
+    EofReader(Rc<RefCell<Read>>, bool),
     EmptyReader,
 }
 
@@ -16,12 +35,65 @@ impl fmt::Debug for BodyReader {
         let name = match *self {
             SizedReader(..) => "SizedReader",
             ChunkReader(..) => "ChunkReader",
+            EofReader(..) => "EofReader",
             EmptyReader => "EmptyReader",
         };
         write!(f, "BodyReader {}", name)
     }
 }
 
+impl BodyReader {
+    /// return the number of body bytes that are known to remain
+    ///
+    /// `None` means the size is unknown, e.g. for a chunked body
+    #[inline]
+    pub fn size_hint(&self) -> Option<usize> {
+        match *self {
+            SizedReader(_, remain) => Some(remain),
+            // the last chunk is read
+            ChunkReader(_, Some(0)) => Some(0),
+            ChunkReader(..) => None,
+            EofReader(_, true) => Some(0),
+            EofReader(..) => None,
+            EmptyReader => Some(0),
+        }
+    }
+
+    // return the size of the rest of the current transfer chunk, the size
+    // line of the next chunk is read if needed, `Some(0)` is the end of the
+    // body. a sized body is a single chunk, `None` if the size is unknown
+    pub(crate) fn chunk_size(&mut self) -> io::Result<Option<usize>> {
+        match *self {
+            SizedReader(_, remain) => Ok(Some(remain)),
+            ChunkReader(ref r, ref mut opt_remaining) => {
+                if opt_remaining.is_none() {
+                    let mut r = r.borrow_mut();
+                    let size = read_chunk_size(&mut *r)?;
+                    if size == 0 {
+                        skip_trailers(&mut *r)?;
+                    }
+                    *opt_remaining = Some(size);
+                }
+                Ok(*opt_remaining)
+            }
+            EofReader(_, true) => Ok(Some(0)),
+            EofReader(..) => Ok(None),
+            EmptyReader => Ok(Some(0)),
+        }
+    }
+
+    // give up the remaining body without reading it, so it's not drained
+    // on drop, the connection can't be reused after this
+    pub(crate) fn discard(&mut self) {
+        match *self {
+            SizedReader(_, ref mut remain) => *remain = 0,
+            ChunkReader(_, ref mut opt_remaining) => *opt_remaining = Some(0),
+            EofReader(_, ref mut eof) => *eof = true,
+            EmptyReader => {}
+        }
+    }
+}
+
 impl Read for BodyReader {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -34,6 +106,11 @@ impl Read for BodyReader {
                 }
                 let mut r = r.borrow_mut();
                 let n = r.read(&mut buf[0..len])?;
+                if n == 0 {
+                    // the peer closed before sending the whole body
+                    let msg = format!("early eof, {} body bytes are missing", *remain);
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+                }
                 *remain -= n;
                 Ok(n)
             }
@@ -48,7 +125,7 @@ impl Read for BodyReader {
 
                 if rem == 0 {
                     if opt_remaining.is_none() {
-                        eat(&mut *r, b"\r\n")?;
+                        skip_trailers(&mut *r)?;
                     }
 
                     *opt_remaining = Some(0);
@@ -78,6 +155,14 @@ impl Read for BodyReader {
                 };
                 Ok(count)
             }
+            EofReader(ref r, ref mut eof) => {
+                if *eof || buf.is_empty() {
+                    return Ok(0);
+                }
+                let n = r.borrow_mut().read(buf)?;
+                *eof = n == 0;
+                Ok(n)
+            }
             EmptyReader => Ok(0),
         }
     }
@@ -121,8 +206,58 @@ fn eat(rdr: &mut Read, bytes: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
+// the max size of the trailer section of a chunked body
+const MAX_TRAILERS_SIZE: usize = 8 * 1024;
+
+/// Consume the trailer headers after the last chunk and the terminating
+/// empty line, so the stream is left at the next request boundary.
+fn skip_trailers(rdr: &mut Read) -> io::Result<()> {
+    let max_line = max_line_length();
+    let mut buf = [0];
+    let mut line_len = 0;
+    for _ in 0..MAX_TRAILERS_SIZE {
+        if rdr.read(&mut buf)? != 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "early eof in chunked trailers",
+            ));
+        }
+        match buf[0] {
+            b'\n' if line_len == 0 => return Ok(()),
+            b'\n' => line_len = 0,
+            b'\r' => {}
+            _ if line_len >= max_line => return Err(line_too_long()),
+            _ => line_len += 1,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "chunked trailers too large",
+    ))
+}
+
+fn line_too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "chunked line too long")
+}
+
+// append a hex digit to the chunk size
+fn push_hex(size: usize, digit: u8) -> io::Result<usize> {
+    if size > usize::MAX >> 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid chunk size line, size overflow",
+        ));
+    }
+    Ok((size << 4) + digit as usize)
+}
+
 /// Chunked chunks start with 1*HEXDIGIT, indicating the size of the chunk.
+///
+/// The whole line including the extensions can't be longer than the max
+/// line length.
 fn read_chunk_size(rdr: &mut Read) -> io::Result<usize> {
+    let max_line = max_line_length();
+    let mut line_len = 0;
     macro_rules! byte (
         ($rdr:ident) => ({
             let mut buf = [0];
@@ -139,19 +274,15 @@ fn read_chunk_size(rdr: &mut Read) -> io::Result<usize> {
     let mut in_ext = false;
     let mut in_chunk_size = true;
     loop {
+        // the final LF is not counted
+        if line_len >= max_line {
+            return Err(line_too_long());
+        }
+        line_len += 1;
         match byte!(rdr) {
-            b @ b'0'...b'9' if in_chunk_size => {
-                size <<= 4;
-                size += (b - b'0') as usize;
-            }
-            b @ b'a'...b'f' if in_chunk_size => {
-                size <<= 4;
-                size += (b + 10 - b'a') as usize;
-            }
-            b @ b'A'...b'F' if in_chunk_size => {
-                size <<= 4;
-                size += (b + 10 - b'A') as usize;
-            }
+            b @ b'0'...b'9' if in_chunk_size => size = push_hex(size, b - b'0')?,
+            b @ b'a'...b'f' if in_chunk_size => size = push_hex(size, b + 10 - b'a')?,
+            b @ b'A'...b'F' if in_chunk_size => size = push_hex(size, b + 10 - b'A')?,
             b'\r' => match byte!(rdr) {
                 b'\n' => break,
                 _ => {
@@ -192,3 +323,83 @@ fn read_chunk_size(rdr: &mut Read) -> io::Result<usize> {
     trace!("chunk size={:?}", size);
     Ok(size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sized_reader_boundary() {
+        let raw = Rc::new(RefCell::new(Cursor::new(b"helloGET / HTTP/1.1".to_vec())));
+        {
+            let mut body = SizedReader(raw.clone(), 5);
+            let mut buf = [0u8; 64];
+            assert_eq!(body.read(&mut buf).unwrap(), 5);
+            assert_eq!(&buf[..5], b"hello");
+            assert_eq!(body.read(&mut buf).unwrap(), 0);
+            assert_eq!(body.size_hint(), Some(0));
+        }
+        // the next request is untouched
+        assert_eq!(raw.borrow().position(), 5);
+    }
+
+    #[test]
+    fn test_chunked_trailers() {
+        let raw = b"5\r\nhello\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\nGET / HTTP/1.1";
+        let raw = Rc::new(RefCell::new(Cursor::new(raw.to_vec())));
+        {
+            let mut body = ChunkReader(raw.clone(), None);
+            let mut s = String::new();
+            body.read_to_string(&mut s).unwrap();
+            assert_eq!(s, "hello");
+        }
+        let raw = raw.borrow();
+        assert_eq!(&raw.get_ref()[raw.position() as usize..], b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn test_sized_reader_early_eof() {
+        let raw = Rc::new(RefCell::new(Cursor::new(b"hel".to_vec())));
+        let mut body = SizedReader(raw, 5);
+        let mut s = String::new();
+        let err = body.read_to_string(&mut s).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(body.size_hint(), Some(2));
+        body.discard();
+    }
+
+    #[test]
+    fn test_eof_reader() {
+        let raw = Rc::new(RefCell::new(Cursor::new(b"hello world".to_vec())));
+        let mut body = EofReader(raw, false);
+        assert_eq!(body.size_hint(), None);
+        let mut s = String::new();
+        body.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello world");
+        assert_eq!(body.size_hint(), Some(0));
+    }
+
+    #[test]
+    fn test_chunked_line_too_long() {
+        set_max_line_length(16);
+        let read_err = |raw: &[u8]| {
+            let raw = Rc::new(RefCell::new(Cursor::new(raw.to_vec())));
+            let mut body = ChunkReader(raw, None);
+            let mut s = String::new();
+            let err = body.read_to_string(&mut s).unwrap_err();
+            body.discard();
+            err
+        };
+
+        // the extension never ends with a CRLF
+        let mut raw = b"5;".to_vec();
+        raw.extend_from_slice(&[b'x'; 1024]);
+        assert_eq!(read_err(&raw).kind(), io::ErrorKind::InvalidData);
+        // too many digits
+        assert_eq!(read_err(&[b'f'; 20]).kind(), io::ErrorKind::InvalidData);
+        let mut raw = b"0\r\nX-Trailer: ".to_vec();
+        raw.extend_from_slice(&[b'x'; 1024]);
+        assert_eq!(read_err(&raw).kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -8,7 +8,10 @@ use self::BodyWriter::*;
 pub enum BodyWriter {
     SizedWriter(Rc<RefCell<Write>>, usize),
     ChunkWriter(Rc<RefCell<Write>>),
+    // the body is delimited by closing the connection
+    CloseWriter(Rc<RefCell<Write>>),
     // this is used to write all the data out when get drop
+    // any body data written to it is discarded
     EmptyWriter(Rc<RefCell<Write>>),
     // this is used as a invalid place holder
     InvalidWriter,
@@ -19,6 +22,7 @@ impl fmt::Debug for BodyWriter {
         let name = match *self {
             SizedWriter(..) => "SizedWriter",
             ChunkWriter(_) => "ChunkWriter",
+            CloseWriter(_) => "CloseWriter",
             EmptyWriter(_) => "EmptyWriter",
             InvalidWriter => "Invalid",
         };
@@ -46,7 +50,12 @@ impl Write for BodyWriter {
                 w.write_all(b"\r\n")?;
                 Ok(chunk_size)
             }
-            EmptyWriter(_) => Ok(0),
+            CloseWriter(ref w) => {
+                let mut w = w.borrow_mut();
+                w.write(buf)
+            }
+            // the body is not allowed, just discard it
+            EmptyWriter(_) => Ok(buf.len()),
             InvalidWriter => unreachable!(),
         }
     }
@@ -62,6 +71,10 @@ impl Write for BodyWriter {
                 let mut w = w.borrow_mut();
                 w.flush()
             }
+            CloseWriter(ref w) => {
+                let mut w = w.borrow_mut();
+                w.flush()
+            }
             EmptyWriter(ref w) => {
                 let mut w = w.borrow_mut();
                 w.flush()
@@ -89,7 +102,7 @@ impl Drop for BodyWriter {
                 w.write_all(b"0\r\n\r\n").ok();
                 w.flush().ok();
             }
-            EmptyWriter(ref w) => {
+            CloseWriter(ref w) | EmptyWriter(ref w) => {
                 let mut w = w.borrow_mut();
                 w.flush().ok();
             }
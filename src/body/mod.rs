@@ -1,4 +1,7 @@
 mod body_reader;
 mod body_writer;
-pub use self::body_reader::BodyReader;
+mod tee_reader;
+pub(crate) use self::body_reader::set_max_line_length;
+pub use self::body_reader::{BodyReader, DEFAULT_MAX_LINE_LENGTH};
 pub use self::body_writer::BodyWriter;
+pub use self::tee_reader::TeeReader;
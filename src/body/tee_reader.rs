@@ -0,0 +1,62 @@
+use std::io::{self, Read, Write};
+
+/// a reader that copies every byte read into a sink
+///
+/// at most `limit` bytes are copied, the rest is only passed to the reader.
+/// a failing sink would stop the copy but not the reading
+#[derive(Debug)]
+pub struct TeeReader<R, W> {
+    reader: R,
+    sink: W,
+    remain: usize,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    /// create a new `TeeReader` that copies at most `limit` bytes into `sink`
+    pub fn new(reader: R, sink: W, limit: usize) -> Self {
+        TeeReader {
+            reader,
+            sink,
+            remain: limit,
+        }
+    }
+
+    /// return the reader and the sink
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.sink)
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::cmp;
+        let n = self.reader.read(buf)?;
+        let len = cmp::min(n, self.remain);
+        if len > 0 {
+            match self.sink.write_all(&buf[..len]) {
+                Ok(_) => self.remain -= len,
+                Err(e) => {
+                    error!("tee body err={}", e);
+                    self.remain = 0;
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tee_limit() {
+        let mut sink = Vec::new();
+        let mut s = String::new();
+        TeeReader::new(&b"hello world"[..], &mut sink, 5)
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "hello world");
+        assert_eq!(sink, b"hello");
+    }
+}
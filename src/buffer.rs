@@ -1,5 +1,5 @@
 use std::cmp;
-use std::io::{self, BufRead, Read, Write};
+use std::io::{self, BufRead, IoSlice, Read, Write};
 
 use bytes::{BufMut, BytesMut};
 
@@ -8,6 +8,10 @@ pub struct BufferIo<T> {
     inner: T,
     reader_buf: BytesMut,
     writer_buf: (Vec<u8>, usize),
+    // `flush` is deferred until calling `flush_buf`
+    defer_flush: bool,
+    // write a large buffer along with the buffered data in one syscall
+    vectored: bool,
 }
 
 const INIT_BUFFER_SIZE: usize = 4096;
@@ -24,6 +28,8 @@ impl<T> BufferIo<T> {
             inner: io,
             reader_buf: BytesMut::with_capacity(cap),
             writer_buf: (vec![0u8; cap], 0),
+            defer_flush: false,
+            vectored: false,
         }
     }
 
@@ -31,6 +37,87 @@ impl<T> BufferIo<T> {
     pub fn inner_mut(&mut self) -> &mut T {
         &mut self.inner
     }
+
+    /// set the max size of the buffered data that is not written out
+    ///
+    /// a write that can't fit writes out the buffer first, so this bounds
+    /// the memory when the peer reads slowly. call it before any write
+    #[inline]
+    pub fn set_write_capacity(&mut self, cap: usize) {
+        debug_assert_eq!(self.writer_buf.1, 0);
+        self.writer_buf.0.resize(cap.max(1), 0);
+    }
+
+    /// write through the data that can't fit with a vectored write
+    ///
+    /// the buffered data and the new data are sent in one `writev`
+    /// instead of copying the new data into the buffer piece by piece
+    #[inline]
+    pub fn set_vectored(&mut self, vectored: bool) {
+        self.vectored = vectored;
+    }
+
+    /// defer the `flush` until calling `flush_buf`
+    ///
+    /// this coalesces the small writes, e.g. pipelined responses
+    #[inline]
+    pub fn set_defer_flush(&mut self, defer: bool) {
+        self.defer_flush = defer;
+    }
+}
+
+impl<T: Write> BufferIo<T> {
+    /// write out all the buffered data
+    #[inline]
+    pub fn flush_buf(&mut self) -> io::Result<()> {
+        let buf = &self.writer_buf.0[0..self.writer_buf.1];
+        self.inner.write_all(buf)?;
+        self.writer_buf.1 = 0;
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> BufferIo<T> {
+    // write the buffered data and then `buf` with vectored writes,
+    // return the number of bytes written from `buf`
+    fn write_through(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer_buf.1;
+        let mut start = 0;
+        while start < len {
+            let ret = {
+                let bufs = [
+                    IoSlice::new(&self.writer_buf.0[start..len]),
+                    IoSlice::new(buf),
+                ];
+                self.inner.write_vectored(&bufs)
+            };
+            match ret {
+                Ok(0) => {
+                    self.consume_written(start);
+                    let msg = "failed to write the buffered data";
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, msg));
+                }
+                Ok(n) => start += n,
+                Err(e) => {
+                    self.consume_written(start);
+                    return Err(e);
+                }
+            }
+        }
+        self.writer_buf.1 = 0;
+        // the last write may go past the buffered data into `buf`
+        match start - len {
+            0 => self.inner.write(buf),
+            n => Ok(n),
+        }
+    }
+
+    // drop the written head of the buffered data
+    fn consume_written(&mut self, n: usize) {
+        let len = self.writer_buf.1;
+        self.writer_buf.0.copy_within(n..len, 0);
+        self.writer_buf.1 = len - n;
+    }
 }
 
 impl<T: Read> BufferIo<T> {
@@ -83,8 +170,11 @@ impl<T: Write> Write for BufferIo<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         use std::ptr;
         let buf_len = self.writer_buf.0.len();
+        if self.vectored && buf.len() > buf_len - self.writer_buf.1 {
+            return self.write_through(buf);
+        }
         if buf_len == self.writer_buf.1 {
-            self.flush()?;
+            self.flush_buf()?;
         }
 
         let remain = buf_len - self.writer_buf.1;
@@ -100,10 +190,10 @@ impl<T: Write> Write for BufferIo<T> {
 
     #[inline]
     fn flush(&mut self) -> io::Result<()> {
-        let buf = &self.writer_buf.0[0..self.writer_buf.1];
-        self.inner.write_all(buf)?;
-        self.writer_buf.1 = 0;
-        Ok(())
+        if self.defer_flush {
+            return Ok(());
+        }
+        self.flush_buf()
     }
 }
 
@@ -178,4 +268,24 @@ mod tests {
         let n = wrt.write(&data).unwrap();
         assert_eq!(n, 40);
     }
+
+    #[test]
+    fn test_write_capacity() {
+        let mut wrt = BufferIo::new(Vec::new());
+        wrt.set_write_capacity(10);
+        wrt.write_all(&[1u8; 25]).unwrap();
+        assert_eq!(wrt.inner_mut().len(), 20);
+    }
+
+    #[test]
+    fn test_write_vectored() {
+        let mut wrt = BufferIo::with_capacity(Vec::new(), 8);
+        wrt.set_vectored(true);
+        wrt.write_all(b"head").unwrap();
+        wrt.write_all(b"a large body").unwrap();
+        assert_eq!(wrt.inner_mut().as_slice(), &b"heada large body"[..]);
+        wrt.write_all(b"tail").unwrap();
+        wrt.flush_buf().unwrap();
+        assert_eq!(wrt.inner_mut().as_slice(), &b"heada large bodytail"[..]);
+    }
 }
@@ -7,7 +7,7 @@ use std::rc::Rc;
 use body::BodyReader;
 use bytes::{Bytes, BytesMut};
 use http::header::*;
-use http::{self, Version};
+use http::{self, StatusCode, Version};
 use httparse;
 
 pub(crate) fn decode(buf: &mut BytesMut) -> io::Result<Option<Response>> {
@@ -81,8 +81,14 @@ impl Response {
             s.parse().expect("failed to parse content length")
         });
 
+        // a HTTP/1.0 server can't send chunked, the body ends with the connection
+        let close_delimited = self.version() == Version::HTTP_10
+            && !self.headers().contains_key(TRANSFER_ENCODING)
+            && self.status() != StatusCode::NO_CONTENT
+            && self.status() != StatusCode::NOT_MODIFIED;
         let body_reader = match size {
             Some(n) => BodyReader::SizedReader(reader, n),
+            None if close_delimited => BodyReader::EofReader(reader, false),
             None => BodyReader::ChunkReader(reader, None),
         };
 
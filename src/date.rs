@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::fmt::{self, Write};
 use std::str;
+use std::time::{self as std_time, SystemTime, UNIX_EPOCH};
 
 use time::{self, Duration};
 
@@ -12,6 +13,38 @@ pub fn now() -> Now {
     Now
 }
 
+/// format the time as an http date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn fmt_http_date(t: SystemTime) -> String {
+    let sec = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    time::at_utc(time::Timespec::new(sec, 0))
+        .rfc822()
+        .to_string()
+}
+
+/// parse an http date
+///
+/// accept the IMF-fixdate format and the obsolete RFC 850 and asctime formats
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let formats = [
+        "%a, %d %b %Y %T GMT",
+        "%A, %d-%b-%y %T GMT",
+        "%a %b %e %T %Y",
+    ];
+    let s = s.trim();
+    let tm = formats
+        .iter()
+        .filter_map(|f| time::strptime(s, f).ok())
+        .next()?;
+    let sec = tm.to_timespec().sec;
+    if sec < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + std_time::Duration::from_secs(sec as u64))
+}
+
 // Gee Alex, doesn't this seem like premature optimization. Well you see there
 // Billy, you're absolutely correct! If your server is *bottlenecked* on
 // rendering the `Date` header, well then boy do I have news for you, you don't
@@ -77,3 +110,18 @@ impl<'a> fmt::Write for LocalBuffer<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_date() {
+        let t = UNIX_EPOCH + std_time::Duration::from_secs(784111777);
+        assert_eq!(fmt_http_date(t), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(t));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(t));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(t));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+}
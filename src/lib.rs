@@ -1,14 +1,27 @@
 extern crate bytes;
+#[cfg(feature = "gzip")]
+extern crate flate2;
 pub extern crate http;
 extern crate httparse;
+#[cfg(target_os = "linux")]
+extern crate libc;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate may;
+#[cfg(feature = "typed-query")]
+extern crate serde;
+#[cfg(all(test, feature = "typed-query"))]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "typed-query")]
+extern crate serde_urlencoded;
+extern crate socket2;
 extern crate time;
 
 pub mod body;
 mod buffer;
 pub mod client;
 mod date;
+mod percent;
 pub mod server;
@@ -0,0 +1,89 @@
+//! percent encoding helpers
+//!
+
+/// decode the percent encoded bytes
+///
+/// return `None` if there is a malformed escape sequence
+pub fn percent_decode(input: &[u8]) -> Option<Vec<u8>> {
+    fn hex(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b + 10 - b'a'),
+            b'A'..=b'F' => Some(b + 10 - b'A'),
+            _ => None,
+        }
+    }
+
+    let mut out = Vec::with_capacity(input.len());
+    let mut iter = input.iter();
+    while let Some(&b) = iter.next() {
+        if b == b'%' {
+            let hi = iter.next().and_then(|&b| hex(b))?;
+            let lo = iter.next().and_then(|&b| hex(b))?;
+            out.push((hi << 4) | lo);
+        } else {
+            out.push(b);
+        }
+    }
+    Some(out)
+}
+
+/// decode the percent encoded bytes into an utf8 string
+#[inline]
+pub fn percent_decode_str(input: &str) -> Option<String> {
+    percent_decode(input.as_bytes()).and_then(|v| String::from_utf8(v).ok())
+}
+
+/// percent encode the bytes that are not kept
+pub fn percent_encode(input: &[u8], keep: fn(u8) -> bool) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut out = String::with_capacity(input.len());
+    for &b in input {
+        if keep(b) {
+            out.push(b as char);
+        } else {
+            out.push('%');
+            out.push(HEX[(b >> 4) as usize] as char);
+            out.push(HEX[(b & 0xf) as usize] as char);
+        }
+    }
+    out
+}
+
+/// the unreserved chars of RFC 3986
+#[inline]
+pub fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b'~'
+}
+
+/// the chars that can be used in an url path and query without encoding
+///
+/// `%` is kept so that the already encoded sequences are not changed
+#[inline]
+pub fn is_url_char(b: u8) -> bool {
+    is_unreserved(b) || b"!$&'()*+,;=:@/?%".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode_str("a%20b%2fc"), Some("a b/c".to_owned()));
+        assert_eq!(percent_decode_str("%E4%BD%A0"), Some("你".to_owned()));
+        assert_eq!(percent_decode_str("100%"), None);
+        assert_eq!(percent_decode_str("%zz"), None);
+        assert_eq!(percent_decode_str("%FF"), None);
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        let s = percent_encode("a b/你?x=1%20".as_bytes(), is_url_char);
+        assert_eq!(s, "a%20b/%E4%BD%A0?x=1%20");
+        assert_eq!(
+            percent_decode_str(&percent_encode(b"a b", is_unreserved)),
+            Some("a b".to_owned())
+        );
+    }
+}
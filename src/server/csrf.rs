@@ -0,0 +1,223 @@
+//! CSRF protection
+//!
+//! a `HttpService` wrapper that checks the unsafe requests with the
+//! double-submit token or the `Origin` allowlist
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::*;
+use http::{Method, StatusCode};
+
+use server::{HttpService, Request, Response};
+
+/// reject the cross-site requests that change the state
+///
+/// the `POST`, `PUT`, `DELETE` and `PATCH` requests pass if the token in
+/// the header equals the one in the cookie, or if the origin is in the
+/// allowlist, otherwise `403` is responded without calling the service.
+/// the other methods always pass, and the token cookie is set if the
+/// client doesn't have one. the cookie is readable by the scripts so
+/// they can copy it to the header
+///
+/// ```no_run
+/// # use may_http::server::*;
+/// # fn app(_req: Request, rsp: &mut Response) {}
+/// let csrf = Csrf::new(app).allowed_origins(&["https://example.com"]);
+/// let server = HttpServer::new(csrf).start("127.0.0.1:8080").unwrap();
+/// server.wait();
+/// ```
+pub struct Csrf<T> {
+    inner: T,
+    cookie_name: String,
+    header_name: HeaderName,
+    allowed_origins: Vec<String>,
+    generator: Box<Fn() -> String + Send + Sync>,
+}
+
+impl<T: HttpService> Csrf<T> {
+    /// wrap the service, the token is sent in the `csrf_token` cookie and
+    /// expected back in the `X-CSRF-Token` header
+    pub fn new(inner: T) -> Self {
+        Csrf {
+            inner,
+            cookie_name: "csrf_token".to_owned(),
+            header_name: HeaderName::from_static("x-csrf-token"),
+            allowed_origins: Vec::new(),
+            generator: Box::new(generate_token),
+        }
+    }
+
+    /// set the name of the token cookie
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    /// set the name of the header that carries the token back
+    ///
+    /// panics if the name is not a valid header name
+    pub fn header_name(mut self, name: &str) -> Self {
+        self.header_name = name.parse().expect("invalid csrf header name");
+        self
+    }
+
+    /// accept the unsafe requests from these origins without the token,
+    /// e.g. `https://example.com`
+    pub fn allowed_origins(mut self, origins: &[&str]) -> Self {
+        self.allowed_origins = origins.iter().map(|o| o.to_string()).collect();
+        self
+    }
+
+    /// set the function that generates a new token
+    ///
+    /// the default one is not a crypto random generator, use your own if
+    /// the token needs to be unpredictable against a strong attacker.
+    /// the token must be a valid cookie value
+    pub fn token_generator<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.generator = Box::new(f);
+        self
+    }
+
+    // check the token or the origin of an unsafe request
+    fn is_allowed(&self, req: &Request) -> bool {
+        if !self.allowed_origins.is_empty() {
+            let origins = self
+                .allowed_origins
+                .iter()
+                .map(|o| o.as_str())
+                .collect::<Vec<_>>();
+            if req.origin_matches(&origins) {
+                return true;
+            }
+        }
+        let cookie = match req.cookie(&self.cookie_name) {
+            Some(c) if !c.is_empty() => c,
+            _ => return false,
+        };
+        match req.headers().get(&self.header_name) {
+            Some(token) => constant_time_eq(token.as_bytes(), cookie.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+impl<T: HttpService> HttpService for Csrf<T> {
+    fn handle(&self, req: Request, rsp: &mut Response) {
+        let unsafe_method = matches!(
+            *req.method(),
+            Method::POST | Method::PUT | Method::DELETE | Method::PATCH
+        );
+        if unsafe_method {
+            if !self.is_allowed(&req) {
+                info!("reject request {:?}: csrf check failed", req);
+                *rsp.status_mut() = StatusCode::FORBIDDEN;
+                return;
+            }
+        } else if req.cookie(&self.cookie_name).is_none_or(|c| c.is_empty()) {
+            let cookie = format!(
+                "{}={}; Path=/; SameSite=Strict",
+                self.cookie_name,
+                (self.generator)()
+            );
+            match cookie.parse() {
+                Ok(cookie) => {
+                    rsp.headers_mut().append(SET_COOKIE, cookie);
+                }
+                Err(_) => error!("invalid csrf cookie {:?}", cookie),
+            }
+        }
+        self.inner.handle(req, rsp)
+    }
+}
+
+// compare without an early exit, so the time doesn't leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 128 bits from the randomly keyed sip hasher, in hex
+fn generate_token() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut token = String::with_capacity(32);
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(nanos);
+        token.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::mock::serve;
+    use server::HttpServer;
+
+    fn app(_req: Request, rsp: &mut Response) {
+        rsp.send(b"done").unwrap();
+    }
+
+    #[test]
+    fn test_csrf() {
+        let csrf = Csrf::new(app)
+            .header_name("x-token")
+            .token_generator(|| "t0k3n".to_owned());
+        let server = HttpServer::new(csrf);
+
+        // a safe request passes and gets the token
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("set-cookie: csrf_token=t0k3n; Path=/; SameSite=Strict\r\n"));
+        assert!(rsp.ends_with("done"));
+        let rsp = serve(
+            &server,
+            b"GET / HTTP/1.1\r\nCookie: csrf_token=t0k3n\r\n\r\n",
+        );
+        assert!(!rsp.contains("set-cookie"));
+
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nCookie: csrf_token=t0k3n\r\nX-Token: t0k3n\r\n\
+              Content-Length: 0\r\n\r\n",
+        );
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.ends_with("done"));
+
+        for input in &[
+            &b"POST / HTTP/1.1\r\nCookie: csrf_token=t0k3n\r\nContent-Length: 0\r\n\r\n"[..],
+            &b"DELETE / HTTP/1.1\r\nCookie: csrf_token=t0k3n\r\nX-Token: other\r\n\r\n"[..],
+            &b"PUT / HTTP/1.1\r\nX-Token: t0k3n\r\nContent-Length: 0\r\n\r\n"[..],
+        ] {
+            let rsp = serve(&server, input);
+            assert!(rsp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+            assert!(!rsp.contains("done"));
+        }
+    }
+
+    #[test]
+    fn test_csrf_origin() {
+        let csrf = Csrf::new(app).allowed_origins(&["https://example.com"]);
+        let server = HttpServer::new(csrf);
+        let input = b"POST / HTTP/1.1\r\nOrigin: https://example.com\r\nContent-Length: 0\r\n\r\n";
+        assert!(serve(&server, input).ends_with("done"));
+        let input = b"POST / HTTP/1.1\r\nOrigin: https://evil.com\r\nContent-Length: 0\r\n\r\n";
+        assert!(serve(&server, input).starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+        assert_eq!(generate_token().len(), 32);
+        assert_ne!(generate_token(), generate_token());
+    }
+}
@@ -0,0 +1,635 @@
+//! Static file serving
+//!
+//! helpers for `Response` to serve files from the local file system
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::*;
+use http::{Method, StatusCode};
+
+use date::fmt_http_date;
+use percent::{is_unreserved, percent_decode_str, percent_encode};
+use server::response::push_html_escaped;
+use server::{Request, Response};
+
+// the result of evaluating the `Range` header against the file size
+#[derive(Debug, PartialEq)]
+enum Range {
+    // serve the whole file
+    Full,
+    // serve the inclusive byte range
+    Partial(u64, u64),
+    // the range can't be satisfied
+    Unsatisfiable,
+}
+
+impl Response {
+    /// serve the file at `path` as the response of `req`
+    ///
+    /// this produces a complete file response, it handles
+    /// - `ETag`/`Last-Modified` validators and the conditional `304`
+    /// - single byte range requests with `206` or `416`
+    /// - the `Content-Type` guessed from the file extension if not set
+    /// - `HEAD` requests that only send the head
+    ///
+    /// a missing file is responded with `404` and a file without access
+    /// permission with `403`, other io errors are returned to the caller
+    pub fn serve_file(&mut self, req: &Request, path: &Path) -> io::Result<()> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return self.set_file_error(e),
+        };
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            *self.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(());
+        }
+
+        let size = meta.len();
+        let modified = meta.modified().ok();
+        let etag = file_etag(size, modified);
+        let last_modified = modified.map(fmt_http_date);
+
+        {
+            let headers = self.headers_mut();
+            headers.insert(ETAG, etag.parse().unwrap());
+            if let Some(ref date) = last_modified {
+                headers.insert(LAST_MODIFIED, date.parse().unwrap());
+            }
+            headers.insert(ACCEPT_RANGES, "bytes".parse().unwrap());
+        }
+
+        if is_not_modified(req, &etag, modified) {
+            *self.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(());
+        }
+
+        let range = match req.headers().get(RANGE) {
+            Some(v) if req.method() == &Method::GET || req.method() == &Method::HEAD => {
+                if is_range_fresh(req, &etag, &last_modified) {
+                    parse_range(v.to_str().unwrap_or(""), size)
+                } else {
+                    Range::Full
+                }
+            }
+            _ => Range::Full,
+        };
+
+        if let Range::Unsatisfiable = range {
+            // the empty body is not the file, so no content type for it
+            self.set_range_not_satisfiable(size);
+            return Ok(());
+        }
+        if !self.headers().contains_key(CONTENT_TYPE) {
+            let mime = guess_mime(path);
+            self.headers_mut()
+                .insert(CONTENT_TYPE, mime.parse().unwrap());
+        }
+
+        let head_only = req.method() == &Method::HEAD;
+        if let Range::Partial(start, end) = range {
+            return self.send_range(&mut file, start, end, size, head_only);
+        }
+
+        self.set_content_length(size as usize);
+        if head_only {
+            return Ok(());
+        }
+        io::copy(&mut file, self)?;
+        Ok(())
+    }
+
+    /// serve the file that the request path maps to under the `root` dir
+    ///
+    /// a dir is served by its `index.html`, a dir path without the trailing
+    /// `/` is redirected to the one with it. if there is no index file and
+    /// `listing` is true, an html list of the entries is generated,
+    /// otherwise it's `403`. the listing exposes all the file names, so
+    /// only enable it for the dirs that are meant to be browsed. paths
+    /// with a `..` segment are responded with `404`
+    pub fn serve_dir(&mut self, req: &Request, root: &Path, listing: bool) -> io::Result<()> {
+        let path = match dir_entry_path(root, req.uri().path()) {
+            Some(path) => path,
+            None => {
+                *self.status_mut() = StatusCode::NOT_FOUND;
+                return Ok(());
+            }
+        };
+        let meta = match fs::metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) => return self.set_file_error(e),
+        };
+        if !meta.is_dir() {
+            return self.serve_file(req, &path);
+        }
+
+        let url_path = req.uri().path();
+        if !url_path.ends_with('/') {
+            let location = match req.uri().query() {
+                Some(q) => format!("{}/?{}", url_path, q),
+                None => format!("{}/", url_path),
+            };
+            *self.status_mut() = StatusCode::MOVED_PERMANENTLY;
+            self.headers_mut()
+                .insert(LOCATION, location.parse().unwrap());
+            self.set_content_length(0);
+            return Ok(());
+        }
+
+        let index = path.join("index.html");
+        if index.is_file() {
+            return self.serve_file(req, &index);
+        }
+        if !listing {
+            *self.status_mut() = StatusCode::FORBIDDEN;
+            return Ok(());
+        }
+        self.send_dir_listing(&path, url_path)
+    }
+
+    // write the html list of the dir entries, the dirs go first
+    fn send_dir_listing(&mut self, dir: &Path, url_path: &str) -> io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                // removed while listing
+                Err(_) => continue,
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push((!meta.is_dir(), name, meta));
+        }
+        entries.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+        let title = percent_decode_str(url_path).unwrap_or_else(|| url_path.to_owned());
+        // render the page first, it's sent with a content-length
+        let mut page =
+            String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of ");
+        push_html_escaped(&mut page, &title);
+        page.push_str("</title></head>\n<body><h1>Index of ");
+        push_html_escaped(&mut page, &title);
+        page.push_str("</h1>\n<table>\n");
+        if url_path != "/" {
+            page.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+        }
+        for (is_file, name, meta) in entries {
+            let slash = if is_file { "" } else { "/" };
+            let href = percent_encode(name.as_bytes(), is_unreserved);
+            page.push_str(&format!("<tr><td><a href=\"{}{}\">", href, slash));
+            push_html_escaped(&mut page, &name);
+            page.push_str(&format!("{}</a></td><td>", slash));
+            if is_file {
+                page.push_str(&meta.len().to_string());
+            } else {
+                page.push('-');
+            }
+            page.push_str("</td><td>");
+            if let Ok(modified) = meta.modified() {
+                page.push_str(&fmt_http_date(modified));
+            }
+            page.push_str("</td></tr>\n");
+        }
+        page.push_str("</table>\n</body></html>\n");
+        self.headers_mut()
+            .insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+        self.send(page.as_bytes())
+    }
+
+    /// send the inclusive byte range `start..=end` of the file at `path`
+    ///
+    /// the response is `206` with the `Content-Range` header, or `416` if
+    /// the range is out of the file. unlike `serve_file` no request headers
+    /// are checked, the caller decides which range to send
+    pub fn send_file_range(&mut self, path: &Path, start: u64, end: u64) -> io::Result<()> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return self.set_file_error(e),
+        };
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            *self.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(());
+        }
+
+        let size = meta.len();
+        if start > end || end >= size {
+            self.set_range_not_satisfiable(size);
+            return Ok(());
+        }
+        if !self.headers().contains_key(CONTENT_TYPE) {
+            let mime = guess_mime(path);
+            self.headers_mut()
+                .insert(CONTENT_TYPE, mime.parse().unwrap());
+        }
+        self.send_range(&mut file, start, end, size, false)
+    }
+
+    // send the inclusive range of the file with `206`
+    fn send_range(
+        &mut self,
+        file: &mut File,
+        start: u64,
+        end: u64,
+        size: u64,
+        head_only: bool,
+    ) -> io::Result<()> {
+        *self.status_mut() = StatusCode::PARTIAL_CONTENT;
+        let content_range = format!("bytes {}-{}/{}", start, end, size);
+        self.headers_mut()
+            .insert(CONTENT_RANGE, content_range.parse().unwrap());
+        let len = end - start + 1;
+        self.set_content_length(len as usize);
+        if head_only {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(len), self)?;
+        Ok(())
+    }
+
+    fn set_range_not_satisfiable(&mut self, size: u64) {
+        *self.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+        let content_range = format!("bytes */{}", size);
+        self.headers_mut()
+            .insert(CONTENT_RANGE, content_range.parse().unwrap());
+        self.set_content_length(0);
+    }
+
+    // set the status according to the file open error
+    fn set_file_error(&mut self, e: io::Error) -> io::Result<()> {
+        match e.kind() {
+            io::ErrorKind::NotFound => *self.status_mut() = StatusCode::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => *self.status_mut() = StatusCode::FORBIDDEN,
+            _ => return Err(e),
+        }
+        Ok(())
+    }
+}
+
+// map the url path to a path under the root, `None` if it may escape the root
+fn dir_entry_path(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path)?;
+    let mut path = root.to_path_buf();
+    for seg in decoded.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => return None,
+            _ if seg.contains('\\') || seg.contains('\0') => return None,
+            _ => path.push(seg),
+        }
+    }
+    Some(path)
+}
+
+// seconds since the unix epoch, http dates don't have sub second precision
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// the etag is made from the file size and the modified time
+fn file_etag(size: u64, modified: Option<SystemTime>) -> String {
+    let secs = modified.map(unix_secs).unwrap_or(0);
+    format!("\"{:x}-{:x}\"", secs, size)
+}
+
+// check the `If-None-Match` and `If-Modified-Since` headers
+fn is_not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if req.method() != &Method::GET && req.method() != &Method::HEAD {
+        return false;
+    }
+
+    // `If-None-Match` takes precedence over `If-Modified-Since`
+    if let Some(v) = req.headers().get(IF_NONE_MATCH) {
+        let v = v.to_str().unwrap_or("");
+        return v.split(',').map(|t| t.trim()).any(|t| {
+            // weak comparison
+            t == "*" || t.trim_start_matches("W/") == etag
+        });
+    }
+
+    match (req.date_header(IF_MODIFIED_SINCE), modified) {
+        (Some(since), Some(modified)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+// check the `If-Range` header, the range is ignored if the validator not match
+fn is_range_fresh(req: &Request, etag: &str, last_modified: &Option<String>) -> bool {
+    match req.headers().get(IF_RANGE) {
+        None => true,
+        Some(v) => {
+            let v = v.as_bytes();
+            // strong comparison
+            v == etag.as_bytes() || last_modified.as_ref().map(|d| d.as_bytes()) == Some(v)
+        }
+    }
+}
+
+// parse the `Range` header value, only a single byte range is supported
+// any range that we don't understand is ignored and the full file is served
+fn parse_range(value: &str, size: u64) -> Range {
+    fn parse_pos(s: &str) -> Option<u64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    }
+
+    // only the `bytes` unit is supported, other units are ignored
+    let mut parts = value.splitn(2, '=');
+    let unit = parts.next().unwrap_or("").trim();
+    let spec = match parts.next() {
+        Some(spec) if unit.eq_ignore_ascii_case("bytes") => spec.trim(),
+        _ => return Range::Full,
+    };
+    if spec.contains(',') {
+        return Range::Full;
+    }
+
+    let mut parts = spec.splitn(2, '-');
+    let first = parts.next().unwrap_or("").trim();
+    let last = match parts.next() {
+        Some(last) => last.trim(),
+        None => return Range::Full,
+    };
+
+    if first.is_empty() {
+        // the suffix range `-n` means the last n bytes
+        return match parse_pos(last) {
+            Some(0) => Range::Unsatisfiable,
+            Some(_) if size == 0 => Range::Unsatisfiable,
+            Some(n) => Range::Partial(size.saturating_sub(n), size - 1),
+            None => Range::Full,
+        };
+    }
+
+    let start = match parse_pos(first) {
+        Some(start) => start,
+        None => return Range::Full,
+    };
+    let end = if last.is_empty() {
+        None
+    } else {
+        match parse_pos(last) {
+            Some(end) if end >= start => Some(end),
+            _ => return Range::Full,
+        }
+    };
+
+    if start >= size {
+        return Range::Unsatisfiable;
+    }
+    let end = end.map_or(size - 1, |end| ::std::cmp::min(end, size - 1));
+    Range::Partial(start, end)
+}
+
+// guess the content type from the file extension
+fn guess_mime(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "text/xml; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::mock::serve;
+    use server::HttpServer;
+    use std::env;
+
+    // create a file in the temp dir with the given content
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn file_server(path: PathBuf) -> HttpServer<impl Fn(Request, &mut Response) + Send + Sync> {
+        HttpServer::new(move |req: Request, rsp: &mut Response| {
+            rsp.serve_file(&req, &path).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Range::Partial(0, 4));
+        assert_eq!(parse_range("bytes=5-", 10), Range::Partial(5, 9));
+        assert_eq!(parse_range("bytes=-3", 10), Range::Partial(7, 9));
+        assert_eq!(parse_range("bytes=8-20", 10), Range::Partial(8, 9));
+        assert_eq!(parse_range("bytes=10-20", 10), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-1", 10), Range::Full);
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), Range::Full);
+        assert_eq!(parse_range("bytes=+1-2", 10), Range::Full);
+        assert_eq!(parse_range("Bytes=1-2", 10), Range::Partial(1, 2));
+        assert_eq!(parse_range("items=0-4", 10), Range::Full);
+    }
+
+    #[test]
+    fn test_serve_file() {
+        let path = temp_file("may_http_serve_file.txt", b"0123456789");
+        let server = file_server(path);
+
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("Content-Length: 10\r\n"));
+        assert!(rsp.contains("content-type: text/plain; charset=utf-8\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n0123456789"));
+
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(rsp.contains("Content-Length: 4\r\n"));
+        assert!(rsp.contains("content-range: bytes 2-5/10\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n2345"));
+
+        // other range units are ignored
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nRange: items=0-10\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n0123456789"));
+
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nRange: bytes=20-\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(rsp.contains("content-range: bytes */10\r\n"));
+
+        let rsp = serve(&server, b"HEAD / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("Content-Length: 10\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_serve_file_conditional() {
+        let path = temp_file("may_http_serve_file_conditional.txt", b"0123456789");
+        let meta = fs::metadata(&path).unwrap();
+        let etag = file_etag(meta.len(), meta.modified().ok());
+        let last_modified = fmt_http_date(meta.modified().unwrap());
+        let server = file_server(path);
+
+        let req = format!("GET / HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag);
+        let rsp = serve(&server, req.as_bytes());
+        assert!(rsp.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n"));
+
+        let req = format!(
+            "GET / HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+            last_modified
+        );
+        let rsp = serve(&server, req.as_bytes());
+        assert!(rsp.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        let req = b"GET / HTTP/1.1\r\nIf-None-Match: \"other\"\r\n\r\n";
+        let rsp = serve(&server, req);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_serve_file_unsatisfiable() {
+        let path = temp_file("may_http_serve_file_416.txt", b"0123456789");
+        let server = file_server(path);
+        for range in &["bytes=10-", "bytes=10-20", "bytes=-0"] {
+            let input = format!("GET / HTTP/1.1\r\nRange: {}\r\n\r\n", range);
+            let rsp = serve(&server, input.as_bytes());
+            assert!(rsp.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+            assert!(rsp.contains("content-range: bytes */10\r\n"));
+            assert!(!rsp.contains("content-type"));
+        }
+
+        let path = temp_file("may_http_serve_file_416_empty.txt", b"");
+        let server = file_server(path);
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nRange: bytes=0-\r\n\r\n");
+        assert!(rsp.contains("content-range: bytes */0\r\n"));
+
+        // the error page keeps the content range
+        let path = temp_file("may_http_serve_file_416_page.txt", b"0123456789");
+        let server = HttpServer::builder(move |req: Request, rsp: &mut Response| {
+            rsp.serve_file(&req, &path).unwrap();
+        })
+        .error_handler(|status, _req| (HeaderMap::new(), status.to_string().into_bytes()))
+        .build();
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\nRange: bytes=99-\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(rsp.contains("content-range: bytes */10\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n416 Range Not Satisfiable"));
+    }
+
+    #[test]
+    fn test_send_file_range() {
+        let path = temp_file("may_http_send_file_range.txt", b"0123456789");
+        let server = HttpServer::new(move |req: Request, rsp: &mut Response| {
+            let (start, end) = match req.uri().path() {
+                "/mid" => (3, 6),
+                _ => (5, 10),
+            };
+            rsp.send_file_range(&path, start, end).unwrap();
+        });
+
+        let rsp = serve(&server, b"GET /mid HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(rsp.contains("Content-Length: 4\r\n"));
+        assert!(rsp.contains("content-range: bytes 3-6/10\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n3456"));
+
+        let rsp = serve(&server, b"GET /out HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(rsp.contains("content-range: bytes */10\r\n"));
+        assert!(rsp.contains("Content-Length: 0\r\n"));
+    }
+
+    #[test]
+    fn test_serve_dir() {
+        let root = env::temp_dir().join("may_http_serve_dir");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("<script>.txt"), b"x").unwrap();
+        fs::write(root.join("site/index.html"), b"<p>index</p>").unwrap();
+
+        let dir = root.clone();
+        let server = HttpServer::new(move |req: Request, rsp: &mut Response| {
+            let listing = !req.uri().path().starts_with("/site");
+            rsp.serve_dir(&req, &dir, listing).unwrap();
+        });
+
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("content-type: text/html; charset=utf-8\r\n"));
+        assert!(rsp.contains("<title>Index of /</title>"));
+        assert!(rsp.contains("<a href=\"a.txt\">a.txt</a></td><td>5</td>"));
+        assert!(rsp.contains("<a href=\"sub%20dir/\">sub dir/</a></td><td>-</td>"));
+        assert!(rsp.contains("<a href=\"%3Cscript%3E.txt\">&lt;script&gt;.txt</a>"));
+        assert!(!rsp.contains("<script>"));
+        assert!(!rsp.contains("href=\"../\""));
+        // the dirs go first
+        assert!(rsp.find("sub%20dir/").unwrap() < rsp.find("a.txt").unwrap());
+
+        let rsp = serve(&server, b"GET /sub%20dir/ HTTP/1.1\r\n\r\n");
+        assert!(rsp.contains("<title>Index of /sub dir/</title>"));
+        assert!(rsp.contains("href=\"../\""));
+
+        let rsp = serve(&server, b"GET /a.txt HTTP/1.1\r\n\r\n");
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+
+        let rsp = serve(&server, b"GET /sub%20dir HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(rsp.contains("location: /sub%20dir/\r\n"));
+
+        let rsp = serve(&server, b"GET /site/ HTTP/1.1\r\n\r\n");
+        assert!(rsp.ends_with("\r\n\r\n<p>index</p>"));
+
+        let rsp = serve(&server, b"GET /../etc/passwd HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let rsp = serve(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_serve_dir_no_listing() {
+        let root = env::temp_dir().join("may_http_serve_dir_no_listing");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        let dir = root.clone();
+        let server = HttpServer::new(move |req: Request, rsp: &mut Response| {
+            rsp.serve_dir(&req, &dir, false).unwrap();
+        });
+
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!rsp.contains("a.txt"));
+        let rsp = serve(&server, b"GET /a.txt HTTP/1.1\r\n\r\n");
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_serve_file_not_found() {
+        let path = env::temp_dir().join("may_http_serve_file_not_exist.txt");
+        let server = file_server(path);
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
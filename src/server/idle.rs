@@ -0,0 +1,142 @@
+//! idle keep-alive connection limiting
+//!
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use may::net::TcpStream;
+
+/// track the idle keep-alive connections of a server
+///
+/// when there are more idle connections than `max`, the one that has been
+/// idle the longest is closed
+pub(crate) struct IdleTracker {
+    max: usize,
+    next_id: AtomicUsize,
+    conns: Mutex<IdleConns>,
+}
+
+struct IdleConns {
+    // the sockets of the tracked connections, used to close them
+    streams: HashMap<usize, TcpStream>,
+    // the ids of the idle connections, the oldest goes first
+    idle: VecDeque<usize>,
+}
+
+impl IdleTracker {
+    pub fn new(max: usize) -> Self {
+        IdleTracker {
+            max,
+            next_id: AtomicUsize::new(0),
+            conns: Mutex::new(IdleConns {
+                streams: HashMap::new(),
+                idle: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// start tracking the connection, it's removed when the guard is dropped
+    pub fn register(tracker: &Arc<Self>, stream: &TcpStream) -> io::Result<IdleGuard> {
+        let stream = stream.try_clone()?;
+        let id = tracker.next_id.fetch_add(1, Ordering::Relaxed);
+        tracker.conns.lock().unwrap().streams.insert(id, stream);
+        Ok(IdleGuard {
+            tracker: tracker.clone(),
+            id,
+        })
+    }
+
+    fn set_idle(&self, id: usize) {
+        let mut conns = self.conns.lock().unwrap();
+        conns.idle.retain(|&i| i != id);
+        conns.idle.push_back(id);
+        while conns.idle.len() > self.max {
+            let oldest = match conns.idle.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            // the blocked read of the connection returns and it's closed
+            if let Some(stream) = conns.streams.get(&oldest) {
+                info!("close the oldest idle connection: too many idle connections");
+                stream.shutdown(net::Shutdown::Both).ok();
+            }
+        }
+    }
+
+    fn set_busy(&self, id: usize) {
+        self.conns.lock().unwrap().idle.retain(|&i| i != id);
+    }
+
+    // the number of the idle connections
+    #[cfg(test)]
+    pub fn idle_count(&self) -> usize {
+        self.conns.lock().unwrap().idle.len()
+    }
+
+    fn remove(&self, id: usize) {
+        let mut conns = self.conns.lock().unwrap();
+        conns.idle.retain(|&i| i != id);
+        conns.streams.remove(&id);
+    }
+}
+
+/// a tracked connection
+pub(crate) struct IdleGuard {
+    tracker: Arc<IdleTracker>,
+    id: usize,
+}
+
+impl IdleGuard {
+    /// the connection waits for the next request
+    pub fn set_idle(&self) {
+        self.tracker.set_idle(self.id);
+    }
+
+    /// the connection got data of the next request
+    pub fn set_busy(&self) {
+        self.tracker.set_busy(self.id);
+    }
+}
+
+impl Drop for IdleGuard {
+    fn drop(&mut self) {
+        self.tracker.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use may::net::TcpListener;
+    use std::io::Read;
+
+    #[test]
+    fn test_idle_tracker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tracker = Arc::new(IdleTracker::new(1));
+        let mut clients = Vec::new();
+        let mut guards = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (conn, _) = listener.accept().unwrap();
+            guards.push((IdleTracker::register(&tracker, &conn).unwrap(), conn));
+        }
+
+        guards[0].0.set_idle();
+        guards[1].0.set_idle();
+        guards[1].0.set_busy();
+        guards[2].0.set_idle();
+        // the first one is closed, the busy one is kept
+        let mut buf = [0; 1];
+        assert_eq!(clients[0].read(&mut buf).unwrap(), 0);
+        assert_eq!(tracker.conns.lock().unwrap().idle, vec![2]);
+
+        drop(guards.pop());
+        let conns = tracker.conns.lock().unwrap();
+        assert!(conns.idle.is_empty());
+        assert_eq!(conns.streams.len(), 2);
+    }
+}
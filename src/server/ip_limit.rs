@@ -0,0 +1,84 @@
+//! per client ip connection limiting
+//!
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// count the alive connections of each client ip
+pub(crate) struct IpLimiter {
+    // the max number of concurrent connections from one ip
+    max: usize,
+    conns: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl IpLimiter {
+    pub fn new(max: usize) -> Self {
+        IpLimiter {
+            max,
+            conns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// take a connection slot of the ip, return `None` if it's all used
+    ///
+    /// the slot is released when the returned guard is dropped
+    pub fn acquire(limiter: &Arc<Self>, ip: IpAddr) -> Option<IpGuard> {
+        let mut conns = limiter.conns.lock().unwrap();
+        let count = conns.get(&ip).cloned().unwrap_or(0);
+        if count >= limiter.max {
+            return None;
+        }
+        conns.insert(ip, count + 1);
+        Some(IpGuard {
+            limiter: limiter.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut conns = self.conns.lock().unwrap();
+        let remove = match conns.get_mut(&ip) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        // don't keep the ips that are gone
+        if remove {
+            conns.remove(&ip);
+        }
+    }
+}
+
+/// an alive connection of the ip, the count is decreased when dropped
+pub(crate) struct IpGuard {
+    limiter: Arc<IpLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for IpGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_limiter() {
+        let limiter = Arc::new(IpLimiter::new(2));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let first = IpLimiter::acquire(&limiter, a).unwrap();
+        let _second = IpLimiter::acquire(&limiter, a).unwrap();
+        assert!(IpLimiter::acquire(&limiter, a).is_none());
+        // other ips are not affected
+        assert!(IpLimiter::acquire(&limiter, b).is_some());
+        drop(first);
+        assert!(IpLimiter::acquire(&limiter, a).is_some());
+        assert_eq!(limiter.conns.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,212 @@
+//! Streaming JSON arrays
+//!
+//! helpers for `Response` to send a large JSON array item by item
+use std::io::{self, Write};
+
+use http::header::*;
+
+use server::Response;
+
+impl Response {
+    /// start streaming a JSON array as the response body
+    ///
+    /// the items are written with the chunked encoding as soon as they are
+    /// pushed, so the client can start parsing before the whole array is
+    /// built. the `Content-Type` is set to `application/json` if not set
+    pub fn json_array_stream(&mut self) -> JsonArrayStream<'_> {
+        if !self.headers().contains_key(CONTENT_TYPE) {
+            self.headers_mut()
+                .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        }
+        JsonArrayStream {
+            rsp: self,
+            items: 0,
+            state: State::Open,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // more items can be pushed
+    Open,
+    // the closing bracket is written
+    Closed,
+    // a write failed, the body is broken
+    Failed,
+}
+
+/// a JSON array that is streamed into the response
+///
+/// created by `Response::json_array_stream`, the array is closed when
+/// `finish` is called or the stream is dropped, an empty stream gives `[]`.
+/// after a write error the array is not closed and all the following
+/// calls return the error, the client would see an incomplete body
+pub struct JsonArrayStream<'a> {
+    rsp: &'a mut Response,
+    items: usize,
+    state: State,
+}
+
+impl<'a> JsonArrayStream<'a> {
+    /// push an already serialized JSON value into the array
+    ///
+    /// the item is flushed to the client right away
+    pub fn push(&mut self, item: &[u8]) -> io::Result<()> {
+        self.check_open()?;
+        let sep: &[u8] = if self.items == 0 { b"[" } else { b"," };
+        let ret = self
+            .rsp
+            .write_all(sep)
+            .and_then(|_| self.rsp.write_all(item))
+            .and_then(|_| self.rsp.flush_now());
+        self.update(ret)?;
+        self.items += 1;
+        Ok(())
+    }
+
+    /// close the array
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.check_open()?;
+        let end: &[u8] = if self.items == 0 { b"[]" } else { b"]" };
+        let ret = self.rsp.write_all(end);
+        self.update(ret)?;
+        self.state = State::Closed;
+        Ok(())
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        match self.state {
+            State::Open => Ok(()),
+            State::Closed => {
+                let msg = "the json array is already closed";
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            }
+            State::Failed => {
+                let msg = "the json array is broken by a previous error";
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            }
+        }
+    }
+
+    fn update(&mut self, ret: io::Result<()>) -> io::Result<()> {
+        if ret.is_err() {
+            self.state = State::Failed;
+        }
+        ret
+    }
+}
+
+impl<'a> Drop for JsonArrayStream<'a> {
+    fn drop(&mut self) {
+        if self.state == State::Open {
+            if let Err(e) = self.close() {
+                error!("failed to close the json array, err={}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn stream_items(items: &[&[u8]]) -> String {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let mut stream = rsp.json_array_stream();
+            for item in items {
+                stream.push(item).unwrap();
+            }
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("content-type: application/json\r\n"));
+        assert!(out.contains("transfer-encoding: chunked\r\n"));
+        // decode the chunked body
+        let body = &out[out.find("\r\n\r\n").unwrap() + 4..];
+        let mut json = String::new();
+        let mut parts = body.split("\r\n");
+        while let Some(size) = parts.next() {
+            if size == "0" {
+                break;
+            }
+            json.push_str(parts.next().unwrap());
+        }
+        json
+    }
+
+    #[test]
+    fn test_json_array_stream() {
+        let json = stream_items(&[b"1", b"{\"a\":\"b\"}", b"[true,null]"]);
+        assert_eq!(json, "[1,{\"a\":\"b\"},[true,null]]");
+        assert_eq!(stream_items(&[]), "[]");
+    }
+
+    #[test]
+    fn test_json_array_flush() {
+        // only the flushed data reaches the client
+        #[derive(Default)]
+        struct Buffered {
+            pending: Vec<u8>,
+            flushed: Vec<u8>,
+        }
+        impl Write for Buffered {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                let pending = ::std::mem::take(&mut self.pending);
+                self.flushed.extend(pending);
+                Ok(())
+            }
+        }
+
+        let out = Rc::new(RefCell::new(Buffered::default()));
+        let mut rsp = Response::new(out.clone());
+        let mut stream = rsp.json_array_stream();
+        stream.push(b"{\"a\":1}").unwrap();
+        {
+            let out = out.borrow();
+            assert!(out.pending.is_empty());
+            let flushed = String::from_utf8(out.flushed.clone()).unwrap();
+            assert!(flushed.contains("\r\n1\r\n[\r\n"));
+            assert!(flushed.ends_with("\r\n{\"a\":1}\r\n"));
+        }
+        stream.push(b"2").unwrap();
+        assert!(String::from_utf8(out.borrow().flushed.clone())
+            .unwrap()
+            .ends_with("\r\n2\r\n"));
+        stream.finish().unwrap();
+    }
+
+    #[test]
+    fn test_json_array_error() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut rsp = Response::new(Rc::new(RefCell::new(Broken)));
+        let mut stream = rsp.json_array_stream();
+        assert_eq!(
+            stream.push(b"1").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(stream.push(b"2").unwrap_err().kind(), io::ErrorKind::Other);
+        assert!(stream.finish().is_err());
+    }
+}
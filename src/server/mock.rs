@@ -0,0 +1,116 @@
+//! in memory connection for testing the server
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Cursor, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use client::HttpClient;
+use server::sockopt::SetReadTimeout;
+use server::{HttpServer, HttpService};
+
+pub struct MockStream {
+    // each part is returned by a separate read
+    input: VecDeque<Cursor<Vec<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+    writes: Rc<Cell<usize>>,
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(mut part) = self.input.pop_front() {
+            let n = part.read(buf)?;
+            if n > 0 {
+                self.input.push_front(part);
+                return Ok(n);
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes.set(self.writes.get() + 1);
+        self.output.borrow_mut().write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        self.writes.set(self.writes.get() + 1);
+        self.output.borrow_mut().write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SetReadTimeout for MockStream {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// feed the raw input to the server and return what it wrote back
+pub fn serve<T>(server: &HttpServer<T>, input: &[u8]) -> String
+where
+    T: HttpService + Send + Sync + 'static,
+{
+    serve_writes(server, input).0
+}
+
+/// same as `serve`, also return the number of write calls on the stream
+pub fn serve_writes<T>(server: &HttpServer<T>, input: &[u8]) -> (String, usize)
+where
+    T: HttpService + Send + Sync + 'static,
+{
+    let (output, writes) = serve_raw(server, &[input]);
+    (String::from_utf8_lossy(&output).into_owned(), writes)
+}
+
+/// same as `serve`, return the raw bytes for a binary response
+#[cfg(feature = "gzip")]
+pub fn serve_bytes<T>(server: &HttpServer<T>, input: &[u8]) -> Vec<u8>
+where
+    T: HttpService + Send + Sync + 'static,
+{
+    serve_raw(server, &[input]).0
+}
+
+/// same as `serve`, the input parts are received by separate reads
+pub fn serve_parts<T>(server: &HttpServer<T>, parts: &[&[u8]]) -> String
+where
+    T: HttpService + Send + Sync + 'static,
+{
+    let output = serve_raw(server, parts).0;
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn serve_raw<T>(server: &HttpServer<T>, parts: &[&[u8]]) -> (Vec<u8>, usize)
+where
+    T: HttpService + Send + Sync + 'static,
+{
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let writes = Rc::new(Cell::new(0));
+    server.serve_connection(
+        MockStream {
+            input: parts.iter().map(|p| Cursor::new(p.to_vec())).collect(),
+            output: output.clone(),
+            writes: writes.clone(),
+        },
+        None,
+        None,
+    );
+    let output = output.borrow().clone();
+    (output, writes.get())
+}
+
+/// send a GET request to the running server and return the response body
+pub fn http_get(addr: SocketAddr) -> String {
+    let mut client = HttpClient::connect(addr).unwrap();
+    let mut rsp = client.get("/".parse().unwrap()).unwrap();
+    let mut body = String::new();
+    rsp.read_to_string(&mut body).unwrap();
+    body
+}
@@ -1,17 +1,45 @@
+mod csrf;
+mod file;
+mod idle;
+mod ip_limit;
+mod json_stream;
+#[cfg(test)]
+mod mock;
+mod multipart;
+mod panic;
+mod preload;
+mod proxy_protocol;
+mod rate_limit;
 mod request;
 mod response;
 mod server_impl;
+mod shutdown;
+#[cfg(unix)]
+mod signal;
+mod sockopt;
+mod sse;
 
 use std::cell::RefCell;
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
 use std::rc::Rc;
 
+use buffer::BufferIo;
 use http::header::*;
-use http::Version;
+use http::{Method, StatusCode, Version};
+use server::response::RawIo;
 
-pub use self::request::Request;
-pub use self::response::Response;
-pub use self::server_impl::HttpServer;
+pub use self::csrf::Csrf;
+pub use self::json_stream::JsonArrayStream;
+pub use self::multipart::Multipart;
+pub use self::preload::preload_links;
+pub use self::request::{BodyChunks, OriginPolicy, Request};
+pub use self::response::{RawStream, Response};
+pub use self::server_impl::{
+    FlushMode, HttpServer, HttpServerBuilder, SecureCookiePolicy, ServerHandle,
+};
+#[cfg(unix)]
+pub use self::signal::Signal;
+pub use self::sse::SseEvent;
 
 /// the http service trait
 /// user code should supply a type that impl the `handle` method for the http server
@@ -33,75 +61,199 @@ where
     }
 }
 
-// when client has expect header, we need to write CONTINUE rsp first
+/// the handler that renders the headers and body of the error responses
+pub(crate) type ErrorHandler = Fn(StatusCode, &Request) -> (HeaderMap, Vec<u8>) + Send + Sync;
+
+// check the expect header, the `100 Continue` is deferred to the first body read
 // return false if need to close the connection
 #[inline]
-fn handle_expect(req: &Request, raw_rsp: &mut Write) -> io::Result<bool> {
-    use http::header::*;
-    use http::{StatusCode, Version};
-    let expect = match req.headers().get(EXPECT) {
-        Some(v) => v.as_bytes(),
-        None => return Ok(true),
-    };
-    if req.version() == Version::HTTP_11 && expect == b"100-continue" {
-        write!(
-            raw_rsp,
-            "{:?} {}\r\n\r\n",
-            Version::HTTP_11,
-            StatusCode::CONTINUE
-        )?;
-        raw_rsp.flush()?;
+fn handle_expect<S: Write>(
+    req: &Request,
+    raw_rsp: &mut BufferIo<S>,
+    version: Version,
+    error_handler: Option<&ErrorHandler>,
+) -> io::Result<bool> {
+    if req.headers().get(EXPECT).is_none() || expects_continue(req) {
+        return Ok(true);
+    }
+
+    // a HTTP/1.0 client doesn't understand the expectation, just ignore it
+    if req.version() != Version::HTTP_11 {
         return Ok(true);
     }
 
-    // don't support expect continue, close the connection
+    // the expectation is not supported, close the connection
+    let status = StatusCode::EXPECTATION_FAILED;
+    let page = error_handler.map(|h| h(status, req));
+    write_error(raw_rsp, version, status, page)?;
     Ok(false)
 }
 
+// check if the client waits for the `100 Continue` before sending the body
+#[inline]
+fn expects_continue(req: &Request) -> bool {
+    req.version() == Version::HTTP_11
+        && req
+            .headers()
+            .get(EXPECT)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
+
+// write the `100 Continue` response
+#[inline]
+fn write_continue<S: Write>(raw_rsp: &mut BufferIo<S>) -> io::Result<()> {
+    write!(
+        raw_rsp,
+        "{:?} {}\r\n\r\n",
+        Version::HTTP_11,
+        StatusCode::CONTINUE
+    )?;
+    // the client is waiting for it, don't defer the flush
+    raw_rsp.flush_buf()
+}
+
+// reject a HTTP/2 client with the `HTTP_1_1_REQUIRED` error, the server
+// preface is an empty `SETTINGS` frame followed by the `GOAWAY` frame
+fn write_h2_goaway<S: Write>(raw_rsp: &mut BufferIo<S>) -> io::Result<()> {
+    // length, type, flags and stream id of the frame headers
+    const SETTINGS: [u8; 9] = [0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+    const GOAWAY: [u8; 9] = [0, 0, 8, 0x7, 0, 0, 0, 0, 0];
+    raw_rsp.write_all(&SETTINGS)?;
+    raw_rsp.write_all(&GOAWAY)?;
+    // no stream is processed, and the error code is `HTTP_1_1_REQUIRED`
+    raw_rsp.write_all(&[0, 0, 0, 0, 0, 0, 0, 0xd])?;
+    raw_rsp.flush_buf()
+}
+
+// write an error response, the connection should be closed after this
+// the body is empty if no page is rendered by the error handler
+#[inline]
+fn write_error<S: Write>(
+    raw_rsp: &mut BufferIo<S>,
+    version: Version,
+    status: StatusCode,
+    page: Option<(HeaderMap, Vec<u8>)>,
+) -> io::Result<()> {
+    let (headers, body) = page.unwrap_or_else(|| (HeaderMap::new(), Vec::new()));
+    write!(
+        raw_rsp,
+        "{:?} {}\r\nDate: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        version,
+        status,
+        ::date::now(),
+        body.len()
+    )?;
+    for (key, value) in headers.iter() {
+        if key == CONTENT_LENGTH || key == CONNECTION {
+            continue;
+        }
+        raw_rsp.write_all(key.as_str().as_bytes())?;
+        raw_rsp.write_all(b": ")?;
+        raw_rsp.write_all(value.as_bytes())?;
+        raw_rsp.write_all(b"\r\n")?;
+    }
+    raw_rsp.write_all(b"\r\n")?;
+    raw_rsp.write_all(&body)?;
+    raw_rsp.flush_buf()
+}
+
+// the server wide defaults of the response content headers
+#[derive(Debug, Clone, Copy, Default)]
+struct ContentDefaults<'a> {
+    charset: Option<&'a str>,
+    content_type: Option<&'a str>,
+}
+
 // return false if need to close the connection
 #[inline]
-fn process_request<S: Read + Write + 'static, T: HttpService>(
+fn process_request<S: RawIo + 'static, T: HttpService>(
     server: &T,
     name: &str,
+    defaults: ContentDefaults,
+    cookie_policy: SecureCookiePolicy,
+    error_handler: Option<&ErrorHandler>,
     mut req: Request,
     stream: Rc<RefCell<S>>,
 ) -> bool {
     req.set_reader(stream.clone());
     let version = req.version();
-    let mut rsp = Response::new(stream);
+    let mut rsp = Response::new(stream.clone());
+    rsp.set_raw_io(stream);
+    if req.method() == &Method::HEAD {
+        rsp.set_head_only();
+    }
+    if version == Version::HTTP_10 {
+        rsp.set_http10_client();
+    }
+    #[cfg(feature = "gzip")]
+    {
+        if req.acceptable_encoding(&["gzip"]).is_some() {
+            rsp.set_accepts_gzip();
+        }
+    }
+    if let Some(charset) = defaults.charset {
+        rsp.set_default_charset(charset);
+    }
+    if let Some(content_type) = defaults.content_type {
+        rsp.set_default_content_type(content_type);
+    }
+    rsp.set_insecure_cookie_policy(cookie_policy);
     let mut keep_alive = should_keep_alive(version, req.headers());
     if !keep_alive {
         rsp.headers_mut()
             .append(CONNECTION, "close".parse().unwrap());
     }
     rsp.headers_mut().append(SERVER, name.parse().unwrap());
-    server.handle(req, &mut rsp);
+    // keep a copy of the request head to render the error page
+    let req_head = error_handler.map(|_| req.clone_head());
+    let render = |status: StatusCode| match (error_handler, req_head.as_ref()) {
+        (Some(h), Some(req)) => Some(h(status, req)),
+        _ => None,
+    };
+    if let Err(e) = panic::catch_panic(|| server.handle(req, &mut rsp)) {
+        error!("{}", e);
+        rsp.write_panic_error(render(StatusCode::INTERNAL_SERVER_ERROR));
+        return false;
+    }
+    if rsp.is_detached() {
+        // the connection is taken by the handler
+        return false;
+    }
+    let status = rsp.status();
+    if (status.is_client_error() || status.is_server_error()) && rsp.is_body_untouched() {
+        if let Some(page) = render(status) {
+            rsp.send_error_page(page);
+        }
+    }
+    // the framing may add `Connection: close` for a HTTP/1.0 client
+    rsp.finish();
     if keep_alive {
         keep_alive = should_keep_alive(version, rsp.headers());
     }
     keep_alive
 }
 
+// iterate the comma separated tokens of all the `Connection` headers
+fn connection_tokens(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(CONNECTION)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+}
+
+// check if the `Connection` headers contain the token, case-insensitive
+fn has_connection_token(headers: &HeaderMap, token: &str) -> bool {
+    connection_tokens(headers).any(|t| t.eq_ignore_ascii_case(token))
+}
+
 #[inline]
 pub fn should_keep_alive(version: Version, headers: &HeaderMap) -> bool {
-    let conn = headers.get_all(CONNECTION);
     match version {
-        Version::HTTP_10 => {
-            for v in conn {
-                if v.as_bytes() == b"keep-alive" {
-                    return true;
-                }
-            }
-            false
-        }
-        Version::HTTP_11 => {
-            for v in conn {
-                if v.as_bytes() == b"close" {
-                    return false;
-                }
-            }
-            true
-        }
+        Version::HTTP_10 => has_connection_token(headers, "keep-alive"),
+        Version::HTTP_11 => !has_connection_token(headers, "close"),
         _ => true,
     }
 }
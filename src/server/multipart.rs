@@ -0,0 +1,230 @@
+//! Multipart form bodies
+//!
+//! helpers for `Request` to read a `multipart/form-data` body
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use http::header::*;
+
+use server::Request;
+
+impl Request {
+    /// return the `multipart/form-data` body of the request
+    ///
+    /// `None` if the content type is not multipart or has no boundary
+    pub fn multipart(&mut self) -> Option<Multipart<'_>> {
+        let boundary = {
+            let content_type = self.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+            let mut parts = content_type.split(';').map(|p| p.trim());
+            if !parts.next()?.eq_ignore_ascii_case("multipart/form-data") {
+                return None;
+            }
+            parts
+                .filter_map(|p| param(p, "boundary"))
+                .next()
+                .filter(|b| !b.is_empty())?
+        };
+        Some(Multipart {
+            req: self,
+            boundary,
+        })
+    }
+}
+
+/// a `multipart/form-data` request body
+///
+/// created by `Request::multipart`
+pub struct Multipart<'a> {
+    req: &'a mut Request,
+    boundary: String,
+}
+
+impl<'a> Multipart<'a> {
+    /// return the boundary that separates the parts
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// read all the text parts into a map of the field names and values
+    ///
+    /// the whole body is read into memory, a body larger than `limit`
+    /// bytes is rejected with an `InvalidData` error. the file parts are
+    /// skipped, or rejected with an `InvalidData` error if `reject_files`
+    /// is true. the later value wins for a repeated field
+    pub fn into_fields(
+        self,
+        reject_files: bool,
+        limit: usize,
+    ) -> io::Result<HashMap<String, String>> {
+        let mut body = Vec::new();
+        self.req
+            .by_ref()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > limit {
+            return Err(invalid("multipart body is too large"));
+        }
+        parse_fields(&body, &self.boundary, reject_files)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// find the first position of the needle
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// return the value of the `key=value` param, the quotes are removed
+fn param(p: &str, key: &str) -> Option<String> {
+    let mut kv = p.splitn(2, '=');
+    let k = kv.next()?.trim();
+    if !k.eq_ignore_ascii_case(key) {
+        return None;
+    }
+    let v = kv.next()?.trim();
+    if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') {
+        Some(
+            v[1..v.len() - 1]
+                .replace("\\\"", "\"")
+                .replace("\\\\", "\\"),
+        )
+    } else {
+        Some(v.to_owned())
+    }
+}
+
+fn parse_fields(
+    body: &[u8],
+    boundary: &str,
+    reject_files: bool,
+) -> io::Result<HashMap<String, String>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut fields = HashMap::new();
+
+    // skip the preamble
+    let start = find(body, delimiter).ok_or_else(|| invalid("no multipart boundary"))?;
+    let mut rest = &body[start + delimiter.len()..];
+    loop {
+        if rest.starts_with(b"--") {
+            // the close delimiter, the epilogue is ignored
+            return Ok(fields);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err(invalid("malformed multipart boundary line"));
+        }
+        rest = &rest[2..];
+
+        let head_end = find(rest, b"\r\n\r\n").ok_or_else(|| invalid("incomplete part headers"))?;
+        let head = String::from_utf8_lossy(&rest[..head_end]);
+        rest = &rest[head_end + 4..];
+
+        let mut close = b"\r\n".to_vec();
+        close.extend_from_slice(delimiter);
+        let end = find(rest, &close).ok_or_else(|| invalid("incomplete multipart body"))?;
+        let content = &rest[..end];
+        rest = &rest[end + close.len()..];
+
+        let disposition = head
+            .split("\r\n")
+            .filter_map(|line| {
+                let mut kv = line.splitn(2, ':');
+                match (kv.next(), kv.next()) {
+                    (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("content-disposition") => {
+                        Some(v.to_owned())
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(|| invalid("part without content-disposition"))?;
+        let params = disposition.split(';').map(|p| p.trim()).skip(1);
+        let mut name = None;
+        let mut is_file = false;
+        for p in params {
+            if let Some(v) = param(p, "name") {
+                name = Some(v);
+            } else if param(p, "filename").is_some() || param(p, "filename*").is_some() {
+                is_file = true;
+            }
+        }
+        let name = name.ok_or_else(|| invalid("part without name"))?;
+
+        if is_file {
+            if reject_files {
+                return Err(invalid("file parts are not allowed"));
+            }
+            continue;
+        }
+        let value = String::from_utf8(content.to_vec())
+            .map_err(|_| invalid("the field value is not utf8"))?;
+        fields.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use bytes::BytesMut;
+    use server::request::decode;
+
+    fn multipart_request(body: &[u8]) -> Request {
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"XyZ\"\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let mut buf = BytesMut::from(head.as_bytes());
+        let mut req = decode(&mut buf, &mut 0).unwrap().unwrap();
+        let body: Rc<RefCell<Read>> = Rc::new(RefCell::new(Cursor::new(body.to_vec())));
+        req.set_reader(body);
+        req
+    }
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"user\"\r\n\r\nalice\r\n\
+        --XyZ\r\n\
+        content-disposition: form-data; name=\"note\"\r\n\
+        Content-Type: text/plain\r\n\r\nline1\r\nline2\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"avatar\"; filename=\"a.png\"\r\n\r\n\x89PNG\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn test_multipart_fields() {
+        let mut req = multipart_request(BODY);
+        let multipart = req.multipart().unwrap();
+        assert_eq!(multipart.boundary(), "XyZ");
+        let fields = multipart.into_fields(false, BODY.len()).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["user"], "alice");
+        assert_eq!(fields["note"], "line1\r\nline2");
+
+        let mut req = multipart_request(BODY);
+        let err = req
+            .multipart()
+            .unwrap()
+            .into_fields(true, BODY.len())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_multipart_limit() {
+        let mut req = multipart_request(BODY);
+        let err = req
+            .multipart()
+            .unwrap()
+            .into_fields(false, BODY.len() - 1)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("too large"));
+    }
+}
@@ -0,0 +1,140 @@
+//! catch the handler panic with its location and backtrace
+//!
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+use may::coroutine;
+
+/// the context of a panic inside the http handler
+pub(crate) struct HandlerPanic {
+    /// the panic message
+    pub message: String,
+    /// the source location of the panic
+    pub location: Option<String>,
+    /// only captured when `RUST_BACKTRACE` is set
+    pub backtrace: Option<Backtrace>,
+}
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "handler panicked at '{}'", self.message)?;
+        if let Some(ref location) = self.location {
+            write!(f, ", {}", location)?;
+        }
+        if let Some(ref backtrace) = self.backtrace {
+            if backtrace.status() == BacktraceStatus::Captured {
+                write!(f, "\nstack backtrace:\n{}", backtrace)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type PanicContext = Option<(Option<String>, Backtrace)>;
+
+// the panic hook saves location and backtrace here for `catch_panic`
+// a coroutine may run on another thread after the unwinding yields,
+// so the coroutine local is used inside a coroutine
+coroutine_local!(static CO_LAST_PANIC: RefCell<PanicContext> = RefCell::new(None));
+thread_local!(static LAST_PANIC: RefCell<PanicContext> = const { RefCell::new(None) });
+
+fn with_last_panic<F: FnOnce(&RefCell<PanicContext>) -> R, R>(f: F) -> R {
+    if coroutine::is_coroutine() {
+        CO_LAST_PANIC.with(f)
+    } else {
+        LAST_PANIC.with(f)
+    }
+}
+
+static HOOK: Once = Once::new();
+
+// install the panic hook, the previous hook is still called
+fn install_hook() {
+    HOOK.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+            // `capture` respects the `RUST_BACKTRACE` env
+            let backtrace = Backtrace::capture();
+            with_last_panic(|p| *p.borrow_mut() = Some((location, backtrace)));
+            prev(info);
+        }));
+    });
+}
+
+fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<Any>".to_owned()
+    }
+}
+
+/// run the function and catch the panic with its context
+pub(crate) fn catch_panic<F: FnOnce() -> R, R>(f: F) -> Result<R, HandlerPanic> {
+    install_hook();
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let (location, backtrace) = match with_last_panic(|p| p.borrow_mut().take()) {
+            Some((location, backtrace)) => (location, Some(backtrace)),
+            None => (None, None),
+        };
+        HandlerPanic {
+            message: panic_message(&*payload),
+            location,
+            backtrace,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        let ret: Result<(), _> = catch_panic(|| panic!("handler failed: {}", 42));
+        let err = ret.err().unwrap();
+        assert_eq!(err.message, "handler failed: 42");
+        assert!(err.location.as_ref().unwrap().contains("panic.rs"));
+        assert!(err.to_string().contains("handler failed: 42"));
+
+        assert_eq!(catch_panic(|| 1).ok(), Some(1));
+    }
+
+    #[test]
+    fn test_catch_panic_yield() {
+        // yield while unwinding, the coroutine may resume on another thread
+        struct YieldOnDrop;
+        impl Drop for YieldOnDrop {
+            fn drop(&mut self) {
+                coroutine::yield_now();
+            }
+        }
+
+        let handles = (0..8)
+            .map(|i| {
+                go!(move || {
+                    let ret: Result<(), _> = catch_panic(|| {
+                        let _guard = YieldOnDrop;
+                        coroutine::yield_now();
+                        panic!("coroutine {} failed", i);
+                    });
+                    ret.err().unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, h) in handles.into_iter().enumerate() {
+            let err = h.join().unwrap();
+            assert_eq!(err.message, format!("coroutine {} failed", i));
+            assert!(err.location.as_ref().unwrap().contains("panic.rs"));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Preload hints
+//!
+//! build the `Link: rel=preload` header for the assets of a page
+use std::io;
+
+use http::header::*;
+
+use server::Response;
+
+/// build a `Link` header value that preloads all the assets
+///
+/// the `as` type is inferred from the extension, e.g. `.css` is a style
+/// and `.js` is a script, fonts are marked `crossorigin` as the browsers
+/// require. an asset of unknown type is an `InvalidInput` error. the value
+/// can also be sent in a `103 Early Hints` by `Response::send_informational`
+pub fn preload_links(assets: &[&str]) -> io::Result<HeaderValue> {
+    let mut links = Vec::with_capacity(assets.len());
+    for url in assets {
+        if url.is_empty() || url.bytes().any(|b| b <= b' ' || b == b'>' || b >= 0x7f) {
+            let msg = format!("invalid preload url {:?}", url);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let (kind, cors) = preload_type(url).ok_or_else(|| {
+            let msg = format!("unknown preload type of {:?}", url);
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+        let cors = if cors { "; crossorigin" } else { "" };
+        links.push(format!("<{}>; rel=preload; as={}{}", url, kind, cors));
+    }
+    HeaderValue::from_str(&links.join(", "))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+// return the `as` type of the asset and if it's fetched in the cors mode
+fn preload_type(url: &str) -> Option<(&'static str, bool)> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let name = path.rsplit('/').next().unwrap_or("");
+    let ext = name.rsplit('.').next().filter(|e| e.len() < name.len())?;
+    let kind = match ext.to_ascii_lowercase().as_str() {
+        "css" => ("style", false),
+        "js" | "mjs" => ("script", false),
+        "woff" | "woff2" | "ttf" | "otf" => ("font", true),
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" | "ico" => ("image", false),
+        "json" => ("fetch", true),
+        _ => return None,
+    };
+    Some(kind)
+}
+
+impl Response {
+    /// add a `Link` header that preloads all the assets
+    ///
+    /// see `preload_links` for how the types are inferred, nothing is
+    /// added if any asset is invalid
+    pub fn add_preload_links(&mut self, assets: &[&str]) -> io::Result<()> {
+        if assets.is_empty() {
+            return Ok(());
+        }
+        let value = preload_links(assets)?;
+        self.headers_mut().append(LINK, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_preload_links() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.add_preload_links(&["/static/app.css", "/static/app.js?v=3"])
+                .unwrap();
+            assert!(rsp.add_preload_links(&["/static/data.bin"]).is_err());
+            assert!(rsp.add_preload_links(&["/a b.css"]).is_err());
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains(
+            "link: </static/app.css>; rel=preload; as=style, \
+             </static/app.js?v=3>; rel=preload; as=script\r\n"
+        ));
+
+        let value = preload_links(&["/fonts/Inter.WOFF2", "/logo.svg"]).unwrap();
+        assert_eq!(
+            value,
+            "</fonts/Inter.WOFF2>; rel=preload; as=font; crossorigin, \
+             </logo.svg>; rel=preload; as=image"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! PROXY protocol preamble
+//!
+//! a TCP load balancer like HAProxy or AWS NLB can prepend the real
+//! client address to the connection, in the text v1 or the binary v2 form
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+// the max length of a v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// parse the preamble at the start of the buffer
+///
+/// return the header length and the source address, the address is
+/// `None` for a `UNKNOWN` or `LOCAL` connection, e.g. a health check.
+/// `Ok(None)` if more data is needed
+pub(crate) fn parse(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    let n = buf.len().min(V2_SIGNATURE.len());
+    if buf[..n] == V2_SIGNATURE[..n] {
+        if n < V2_SIGNATURE.len() {
+            return Ok(None);
+        }
+        return parse_v2(buf);
+    }
+    let n = buf.len().min(6);
+    if buf[..n] == b"PROXY "[..n] {
+        if n < 6 {
+            return Ok(None);
+        }
+        return parse_v1(buf);
+    }
+    Err(invalid("missing the PROXY protocol header"))
+}
+
+// e.g. `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`
+fn parse_v1(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    let end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) if end + 2 <= V1_MAX_LEN => end,
+        Some(_) => return Err(invalid("the PROXY v1 header is too long")),
+        None if buf.len() >= V1_MAX_LEN => return Err(invalid("the PROXY v1 header is too long")),
+        None => return Ok(None),
+    };
+    let line = str::from_utf8(&buf[..end]).map_err(|_| invalid("invalid PROXY v1 header"))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+    let addr = match parts.get(1) {
+        Some(&"UNKNOWN") => None,
+        Some(&"TCP4") | Some(&"TCP6") if parts.len() == 6 => {
+            let ip = parts[2]
+                .parse::<IpAddr>()
+                .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            if ip.is_ipv4() != (parts[1] == "TCP4") {
+                return Err(invalid("the PROXY v1 address doesn't match the family"));
+            }
+            let port = parts[4]
+                .parse::<u16>()
+                .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("invalid PROXY v1 header")),
+    };
+    Ok(Some((end + 2, addr)))
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+    let ver_cmd = buf[12];
+    let family = buf[13];
+    let len = 16 + (((buf[14] as usize) << 8) | buf[15] as usize);
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let body = &buf[16..len];
+    let addr = match (ver_cmd & 0xf, family >> 4) {
+        // a connection made by the proxy itself
+        (0, _) => None,
+        // TCP or UDP over IPv4
+        (1, 1) => {
+            if body.len() < 12 {
+                return Err(invalid("truncated PROXY v2 ipv4 address"));
+            }
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = ((body[8] as u16) << 8) | body[9] as u16;
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        // TCP or UDP over IPv6
+        (1, 2) => {
+            if body.len() < 36 {
+                return Err(invalid("truncated PROXY v2 ipv6 address"));
+            }
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = ((body[32] as u16) << 8) | body[33] as u16;
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        // unspecified or unix socket, there is no ip to use
+        (1, _) => None,
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    };
+    Ok(Some((len, addr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        let buf = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+        let (len, addr) = parse(buf).unwrap().unwrap();
+        assert_eq!(&buf[len..], b"GET /");
+        assert_eq!(addr, Some("192.168.0.1:56324".parse().unwrap()));
+
+        let buf = b"PROXY TCP6 ::1 ::2 8000 80\r\n";
+        let (_, addr) = parse(buf).unwrap().unwrap();
+        assert_eq!(addr, Some("[::1]:8000".parse().unwrap()));
+
+        let (len, addr) = parse(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!((len, addr), (15, None));
+
+        assert!(parse(b"PROX").unwrap().is_none());
+        assert!(parse(b"PROXY TCP4 1.2.3.4").unwrap().is_none());
+        assert!(parse(b"PROXY TCP4 ::1 ::2 1 2\r\n").is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        // PROXY over TCP4, 12 bytes of addresses
+        buf.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        buf.extend_from_slice(&[10, 0, 0, 7, 10, 0, 0, 1, 0x1f, 0x90, 0, 80]);
+        buf.extend_from_slice(b"GET /");
+        assert!(parse(&buf[..20]).unwrap().is_none());
+        let (len, addr) = parse(&buf).unwrap().unwrap();
+        assert_eq!(&buf[len..], b"GET /");
+        assert_eq!(addr, Some("10.0.0.7:8080".parse().unwrap()));
+
+        // LOCAL has no address
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&buf).unwrap().unwrap(), (16, None));
+    }
+}
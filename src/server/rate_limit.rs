@@ -0,0 +1,80 @@
+//! global request rate limiting
+//!
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// a token bucket shared by all the connections of a server
+///
+/// the bucket holds at most `rate` tokens and is refilled with `rate`
+/// tokens per second. it's tracked as the theoretical arrival time of
+/// the next request, so a single atomic is enough
+pub(crate) struct RateLimiter {
+    start: Instant,
+    // nanos between two tokens
+    interval: u64,
+    // nanos that the arrival time can run ahead of now, the bucket size
+    burst: u64,
+    // the theoretical arrival time in nanos since `start`
+    tat: AtomicU64,
+}
+
+impl RateLimiter {
+    /// create a limiter that allows `rate` requests per second
+    pub fn new(rate: u32) -> Self {
+        let rate = u64::from(rate.max(1));
+        let interval = 1_000_000_000 / rate;
+        RateLimiter {
+            start: Instant::now(),
+            interval,
+            burst: interval * rate,
+            tat: AtomicU64::new(0),
+        }
+    }
+
+    /// take a token, return false if the rate is exceeded
+    pub fn acquire(&self) -> bool {
+        self.acquire_at(self.start.elapsed())
+    }
+
+    fn acquire_at(&self, now: Duration) -> bool {
+        let now = now.as_secs() * 1_000_000_000 + u64::from(now.subsec_nanos());
+        let mut tat = self.tat.load(Ordering::Acquire);
+        loop {
+            let next = tat.max(now) + self.interval;
+            if next - now > self.burst {
+                return false;
+            }
+            match self
+                .tat
+                .compare_exchange_weak(tat, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(cur) => tat = cur,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(10);
+        let now = Duration::from_secs(1);
+        for _ in 0..10 {
+            assert!(limiter.acquire_at(now));
+        }
+        assert!(!limiter.acquire_at(now));
+        // one token is refilled every 100ms
+        assert!(limiter.acquire_at(now + Duration::from_millis(100)));
+        assert!(!limiter.acquire_at(now + Duration::from_millis(150)));
+        // the bucket is full again after a second
+        let later = now + Duration::from_secs(5);
+        for _ in 0..10 {
+            assert!(limiter.acquire_at(later));
+        }
+        assert!(!limiter.acquire_at(later));
+    }
+}
@@ -1,36 +1,105 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fmt;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
+use std::time::SystemTime;
 
-use body::BodyReader;
+use body::{BodyReader, TeeReader};
 use bytes::{Bytes, BytesMut};
+use date::parse_http_date;
+#[cfg(feature = "gzip")]
+use flate2::read::{GzDecoder, ZlibDecoder};
 use http::header::*;
-use http::{self, Method, Version};
+use http::{self, Method, StatusCode, Version};
 use httparse;
+#[cfg(feature = "typed-query")]
+use serde::de::DeserializeOwned;
 
-pub(crate) fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
+// the max number of bytes of empty lines allowed before the request line
+const MAX_LEADING_CRLF: usize = 16;
+
+// the start of the connection preface of a HTTP/2 client with prior knowledge
+const H2_PREFACE_START: &[u8] = b"PRI * HTTP/2.0\r\n";
+
+// check if the data is the HTTP/2 connection preface instead of a request
+//
+// the preface looks like a request with the `PRI` method but it's not
+// HTTP/1.x, it's rejected by `decode` as malformed
+pub(crate) fn is_h2_preface(buf: &[u8]) -> bool {
+    buf.starts_with(H2_PREFACE_START)
+}
+
+// `skipped` is the number of empty lines already skipped on the connection
+pub(crate) fn decode(buf: &mut BytesMut, skipped: &mut usize) -> io::Result<Option<Request>> {
+    // `base` is the start of the parsed buffer, a small buffer is stored
+    // inline and moved by `split_to`, so don't use the address of `buf`
     #[inline]
-    fn get_slice(buf: &Bytes, data: &[u8]) -> Bytes {
-        let begin = data.as_ptr() as usize - buf.as_ptr() as usize;
+    fn get_slice(buf: &Bytes, base: usize, data: &[u8]) -> Bytes {
+        let begin = data.as_ptr() as usize - base;
         buf.slice(begin, begin + data.len())
     }
 
+    // ignore the empty lines before the request line, RFC 7230 3.5
+    // they are counted across the reads until a request is parsed
+    let skip = buf
+        .iter()
+        .take_while(|&&b| b == b'\r' || b == b'\n')
+        .count();
+    *skipped += skip;
+    if *skipped > MAX_LEADING_CRLF {
+        let msg = "too many empty lines before the request line";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    if skip > 0 {
+        buf.split_to(skip);
+    }
+
+    let base = buf.as_ptr() as usize;
+
     let mut headers = [httparse::EMPTY_HEADER; 64];
     let mut r = httparse::Request::new(&mut headers);
-    let status = r.parse(buf).map_err(|e| {
-        let msg = format!("failed to parse http request: {:?}", e);
-        io::Error::new(io::ErrorKind::Other, msg)
-    })?;
+    let status = match r.parse(buf) {
+        Ok(status) => status,
+        // a legacy client may fold the header values, unfold and try again
+        Err(_) if unfold_headers(buf) => {
+            return decode(buf, skipped).map(|req| {
+                req.map(|mut req| {
+                    req.folded = true;
+                    req
+                })
+            });
+        }
+        // wait for the whole head, the folding is only undone once
+        Err(_) if head_end(buf).is_none() && has_folding(buf) => return Ok(None),
+        Err(e) => {
+            let msg = format!("failed to parse http request: {:?}", e);
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+    };
 
     let bytes = match status {
         httparse::Status::Complete(amt) => {
+            *skipped = 0;
             let buf = unsafe { &mut *(buf as *const _ as *mut BytesMut) };
             buf.split_to(amt).freeze()
         }
         httparse::Status::Partial => return Ok(None),
     };
+    // the parser also takes a bare LF as the line ending
+    let bare_lf = bytes
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b == b'\n' && (i == 0 || bytes[i - 1] != b'\r'));
+
+    check_transfer_encoding(&*r.headers)?;
+
+    // an extension method like `PURGE` is fine as long as it's a valid token
+    let method = Method::from_bytes(r.method.unwrap_or("").as_bytes()).map_err(|_| {
+        let msg = format!("invalid request method {:?}", r.method);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    })?;
 
     let version = match r.version {
         Some(v) => {
@@ -49,52 +118,1022 @@ pub(crate) fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
 
     let mut req_builder = http::Request::builder();
     req_builder
-        .method(r.method.unwrap())
-        .uri(get_slice(&bytes, r.path.unwrap().as_bytes())) // can be optimized with Bytes
+        .method(method)
+        .uri(get_slice(&bytes, base, r.path.unwrap().as_bytes())) // can be optimized with Bytes
         .version(version);
 
     for header in r.headers.iter() {
-        let value = unsafe { HeaderValue::from_shared_unchecked(get_slice(&bytes, header.value)) };
+        let value =
+            unsafe { HeaderValue::from_shared_unchecked(get_slice(&bytes, base, header.value)) };
         req_builder.header(header.name, value);
     }
 
     req_builder
         .body(BodyReader::EmptyReader)
-        .map(|req| Some(Request(req)))
+        .map(|req| {
+            Some(Request {
+                raw_req: req,
+                data: bytes,
+                trust_proxy: false,
+                folded: false,
+                bare_lf,
+                on_continue: None,
+                body_limit: None,
+                body_left: None,
+                remote_addr: None,
+            })
+        })
         .map_err(|e| {
             let msg = format!("failed to build http request: {:?}", e);
             io::Error::new(io::ErrorKind::Other, msg)
         })
 }
 
+// a repeated transfer-encoding may be read differently by a proxy in front,
+// reject it to avoid the request smuggling
+fn check_transfer_encoding(headers: &[httparse::Header]) -> io::Result<()> {
+    let mut lines = 0;
+    let mut chunked = 0;
+    for h in headers {
+        if !h.name.eq_ignore_ascii_case("transfer-encoding") {
+            continue;
+        }
+        lines += 1;
+        chunked += h
+            .value
+            .split(|&b| b == b',')
+            .filter(|c| trim_ows(c).eq_ignore_ascii_case(b"chunked"))
+            .count();
+    }
+    if lines > 1 {
+        let msg = "multiple transfer-encoding headers";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    if chunked > 1 {
+        let msg = "chunked is applied more than once";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    Ok(())
+}
+
+// return the `scheme://host[:port]` part of the absolute url,
+// empty if it's not absolute
+fn url_origin(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(i) => i + 3,
+        None => return "",
+    };
+    let end = url[rest..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |i| rest + i);
+    &url[..end]
+}
+
+// trim the spaces and tabs around the value
+fn trim_ows(mut v: &[u8]) -> &[u8] {
+    while let Some((&b, rest)) = v.split_first() {
+        if b != b' ' && b != b'\t' {
+            break;
+        }
+        v = rest;
+    }
+    while let Some((&b, rest)) = v.split_last() {
+        if b != b' ' && b != b'\t' {
+            break;
+        }
+        v = rest;
+    }
+    v
+}
+
+// the headers that are only meaningful for a single connection
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[inline]
+fn is_ws(b: Option<&u8>) -> bool {
+    b == Some(&b' ') || b == Some(&b'\t')
+}
+
+// the end of the request head if it's all received
+fn head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+// check if a line of the received part starts with a space or a tab
+fn has_folding(buf: &[u8]) -> bool {
+    buf.windows(3)
+        .any(|w| &w[..2] == b"\r\n" && is_ws(w.get(2)))
+}
+
+// replace the obsolete line folding in the header values with a single space
+// return false if there is nothing to unfold or the head is not complete,
+// a partial head is left as is so the folding is still seen on the next try
+fn unfold_headers(buf: &mut BytesMut) -> bool {
+    let head_end = match head_end(buf) {
+        Some(end) => end,
+        None => return false,
+    };
+    // the folding can't happen at the request line
+    let start = match buf[..head_end].iter().position(|&b| b == b'\n') {
+        Some(i) => i + 1,
+        None => return false,
+    };
+
+    let head = &buf[..head_end];
+    let mut out = Vec::with_capacity(buf.len());
+    out.extend_from_slice(&head[..start]);
+    let mut folded = false;
+    let mut i = start;
+    while i < head.len() {
+        if head[i] == b'\r' && head.get(i + 1) == Some(&b'\n') && is_ws(head.get(i + 2)) {
+            while is_ws(out.last()) {
+                out.pop();
+            }
+            i += 2;
+            while is_ws(head.get(i)) {
+                i += 1;
+            }
+            out.push(b' ');
+            folded = true;
+        } else {
+            out.push(head[i]);
+            i += 1;
+        }
+    }
+    if !folded {
+        return false;
+    }
+
+    out.extend_from_slice(&buf[head_end..]);
+    *buf = BytesMut::from(out);
+    true
+}
+
 /// http server request
 /// a thin wraper to http::Request
 /// impl Read for reading http request body
-pub struct Request(http::Request<BodyReader>);
+pub struct Request {
+    // the raw http request
+    raw_req: http::Request<BodyReader>,
+    // the raw bytes of the request head
+    data: Bytes,
+    // trust the `X-Forwarded-*` headers set by the proxy
+    trust_proxy: bool,
+    // the header values had the obsolete line folding
+    folded: bool,
+    // a line of the head ends with a LF without the CR
+    bare_lf: bool,
+    // write the deferred `100 Continue` before the body is read,
+    // it's shared with the body reader set by `set_reader`
+    on_continue: Option<ContinueHook>,
+    // the max body size and the flag that is set once it's exceeded
+    body_limit: Option<(usize, Rc<Cell<bool>>)>,
+    // set to the size of the unread body when dropped,
+    // `None` if it's not known or can't be drained
+    body_left: Option<Rc<Cell<Option<usize>>>>,
+    // the address of the client, recovered from the PROXY protocol if enabled
+    remote_addr: Option<SocketAddr>,
+}
+
+/// how `Request::origin_matches_with` treats a request without a usable origin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OriginPolicy {
+    /// accept a request without both the `Origin` and `Referer` headers,
+    /// e.g. from a non-browser client
+    pub allow_missing: bool,
+    /// accept the `null` origin, e.g. from a sandboxed iframe or a
+    /// `file://` page
+    pub allow_null: bool,
+}
 
 impl Request {
     // set the body reader
     // this function would be called by the server to
     // set a proper `BodyReader` according to the request
     pub(crate) fn set_reader(&mut self, reader: Rc<RefCell<Read>>) {
-        use std::str;
-
         if self.method() == &Method::GET || self.method() == &Method::HEAD {
             return;
         }
 
-        let size = self.headers().get(CONTENT_LENGTH).map(|v| {
-            let s = unsafe { str::from_utf8_unchecked(v.as_bytes()) };
-            s.parse().expect("failed to parse content length")
-        });
+        let size = match self.content_length() {
+            Ok(size) => size,
+            // rejected by the server before this, don't read any body
+            Err(_) => return,
+        };
+        // no body without the framing headers, RFC 7230 3.3.3
+        if size.is_none() && !self.headers().contains_key(TRANSFER_ENCODING) {
+            return;
+        }
+
+        // every read of the body goes through the reader, so does the hook
+        let reader: Rc<RefCell<Read>> = match self.on_continue {
+            Some(ref hook) => Rc::new(RefCell::new(ContinueReader {
+                inner: reader,
+                hook: hook.clone(),
+            })),
+            None => reader,
+        };
+        let reader: Rc<RefCell<Read>> = match self.body_limit {
+            Some((limit, ref exceeded)) => {
+                // fail at the first read if the declared size is over the limit
+                if size.is_some_and(|n| n > limit) {
+                    exceeded.set(true);
+                }
+                Rc::new(RefCell::new(LimitReader {
+                    inner: reader,
+                    remain: limit,
+                    exceeded: exceeded.clone(),
+                }))
+            }
+            None => reader,
+        };
 
         let body_reader = match size {
             Some(n) => BodyReader::SizedReader(reader, n),
+            None if self.is_identity_te() => BodyReader::EmptyReader,
             None => BodyReader::ChunkReader(reader, None),
         };
 
         *self.body_mut() = body_reader;
     }
+
+    /// return the value of the `Content-Length` header
+    ///
+    /// only plain ascii digits are accepted, a value like `12abc`, `+5` or
+    /// `0x10` or one that overflows is an `InvalidData` error. the server
+    /// responds `400 Bad Request` to such a request
+    pub fn content_length(&self) -> io::Result<Option<usize>> {
+        if !self.headers().contains_key(CONTENT_LENGTH) {
+            return Ok(None);
+        }
+        match self.header_u64(CONTENT_LENGTH) {
+            Some(n) if n <= usize::MAX as u64 => Ok(Some(n as usize)),
+            _ => {
+                let msg = "invalid content-length";
+                Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+            }
+        }
+    }
+
+    /// parse the header value as an unsigned integer, e.g. `Age`
+    ///
+    /// only plain ascii digits are accepted, `None` if the header is
+    /// missing, malformed or overflows. the first one is used if repeated
+    pub fn header_u64<K: AsHeaderName>(&self, name: K) -> Option<u64> {
+        let value = self.headers().get(name)?.as_bytes();
+        if value.is_empty() || !value.iter().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        // the digits are valid utf8
+        ::std::str::from_utf8(value).ok()?.parse().ok()
+    }
+
+    // `Transfer-Encoding: identity` means no transfer coding at all,
+    // it's treated as if the header is absent
+    fn is_identity_te(&self) -> bool {
+        let mut codings = self
+            .headers()
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .peekable();
+        codings.peek().is_some() && codings.all(|c| c.eq_ignore_ascii_case("identity"))
+    }
+
+    // set the max body size, reading more than that is an error and
+    // `exceeded` is set. it must be called before `set_reader`
+    pub(crate) fn set_body_limit(&mut self, limit: usize, exceeded: Rc<Cell<bool>>) {
+        self.body_limit = Some((limit, exceeded));
+    }
+
+    // set the callback that sends the `100 Continue` at the first body read
+    // it must be called before `set_reader`
+    pub(crate) fn set_continue(&mut self, f: Box<FnMut() -> io::Result<()>>) {
+        self.on_continue = Some(Rc::new(RefCell::new(Some(f))));
+    }
+
+    // set the cell that reports the size of the unread body when dropped,
+    // then the body is left to the server instead of drained on drop
+    pub(crate) fn set_body_left(&mut self, left: Rc<Cell<Option<usize>>>) {
+        self.body_left = Some(left);
+    }
+
+    /// check the request against the stricter rules of RFC 7230
+    ///
+    /// invalid method tokens, invalid header names and whitespace before
+    /// the header colon are already rejected by the parser, this adds:
+    ///
+    /// - HTTP/1.1 requests must have exactly one `Host` header
+    /// - `Content-Length` and `Transfer-Encoding` must not be both present,
+    ///   unless the transfer coding is `identity`
+    /// - `Content-Length` must not be repeated
+    /// - the asterisk form target is only allowed for `OPTIONS`
+    /// - the authority form target is only allowed for `CONNECT`
+    /// - the header values must not use the obsolete line folding
+    /// - the lines must end with CRLF, not a bare LF
+    pub fn validate(&self) -> io::Result<()> {
+        fn invalid(msg: &str) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+        }
+
+        if self.folded {
+            return invalid("obsolete line folding in the header values");
+        }
+
+        if self.bare_lf {
+            return invalid("bare LF line ending in the request head");
+        }
+
+        let headers = self.headers();
+        let hosts = headers.get_all(HOST).iter().count();
+        if hosts > 1 || (hosts == 0 && self.version() == Version::HTTP_11) {
+            return invalid("expect exactly one host header");
+        }
+
+        if headers.contains_key(TRANSFER_ENCODING)
+            && !self.is_identity_te()
+            && headers.contains_key(CONTENT_LENGTH)
+        {
+            return invalid("both content-length and transfer-encoding present");
+        }
+
+        if headers.get_all(CONTENT_LENGTH).iter().count() > 1 {
+            return invalid("multiple content-length headers");
+        }
+
+        let uri = self.uri();
+        if uri.path() == "*" && self.method() != &Method::OPTIONS {
+            return invalid("asterisk form is only allowed for OPTIONS");
+        }
+
+        if uri.scheme_part().is_none()
+            && uri.authority_part().is_some()
+            && self.method() != &Method::CONNECT
+        {
+            return invalid("authority form is only allowed for CONNECT");
+        }
+
+        Ok(())
+    }
+
+    /// iterate the tokens of the `Connection` header
+    ///
+    /// multiple headers and comma separated lists are all flattened, the
+    /// tokens are case-insensitive so they are given in lower case
+    #[inline]
+    pub fn connection_tokens<'a>(&'a self) -> impl Iterator<Item = String> + 'a {
+        super::connection_tokens(self.headers()).map(|t| t.to_ascii_lowercase())
+    }
+
+    /// check if the `Connection` header contains the token, case-insensitive
+    #[inline]
+    pub fn has_connection_token(&self, token: &str) -> bool {
+        super::has_connection_token(self.headers(), token)
+    }
+
+    /// check if the header is hop-by-hop, case-insensitive
+    ///
+    /// these are the standard hop-by-hop headers plus the ones listed in
+    /// the `Connection` header, a proxy should strip them when forwarding
+    pub fn is_hop_by_hop(&self, name: &str) -> bool {
+        HOP_BY_HOP_HEADERS
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+            || self.has_connection_token(name)
+    }
+
+    /// parse the header that carries an http date, e.g. `If-Modified-Since`
+    ///
+    /// return `None` if the header is missing or the date is malformed
+    pub fn date_header<K: AsHeaderName>(&self, name: K) -> Option<SystemTime> {
+        self.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date)
+    }
+
+    /// evaluate the `If-Match` and `If-Unmodified-Since` preconditions
+    ///
+    /// `etag` and `modified` are the validators of the current resource,
+    /// `None` if the resource doesn't exist or has no such validator.
+    /// return `Some(412)` if the precondition fails, or `None` to proceed.
+    /// `If-Unmodified-Since` is ignored when `If-Match` is present
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&str>,
+        modified: Option<SystemTime>,
+    ) -> Option<StatusCode> {
+        if let Some(v) = self.headers().get(IF_MATCH) {
+            let v = v.to_str().unwrap_or("");
+            // strong comparison, a weak tag never matches
+            let matched = etag.is_some_and(|etag| {
+                v.split(',')
+                    .map(|t| t.trim())
+                    .any(|t| t == "*" || (!t.starts_with("W/") && t == etag))
+            });
+            return if matched {
+                None
+            } else {
+                Some(StatusCode::PRECONDITION_FAILED)
+            };
+        }
+
+        match (self.date_header(IF_UNMODIFIED_SINCE), modified) {
+            // http dates don't have sub second precision
+            (Some(since), Some(modified)) => match modified.duration_since(since) {
+                Ok(d) if d.as_secs() > 0 => Some(StatusCode::PRECONDITION_FAILED),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// parse the `TE` header into the transfer-codings and their quality
+    ///
+    /// the quality is `1.0` if not specified, e.g. `TE: trailers, gzip;q=0.5`
+    /// gives `[("trailers", 1.0), ("gzip", 0.5)]`
+    pub fn te(&self) -> Vec<(&str, f32)> {
+        self.quality_list(TE)
+    }
+
+    // parse a comma separated list of values with the optional `q` params
+    fn quality_list(&self, name: HeaderName) -> Vec<(&str, f32)> {
+        self.headers()
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|item| {
+                let mut parts = item.split(';').map(|p| p.trim());
+                let value = parts.next().filter(|c| !c.is_empty())?;
+                let q = parts
+                    .filter_map(|p| {
+                        let mut kv = p.splitn(2, '=');
+                        match (kv.next(), kv.next()) {
+                            (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("q") => {
+                                v.trim().parse().ok()
+                            }
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .unwrap_or(1.0);
+                Some((value, q))
+            })
+            .collect()
+    }
+
+    /// choose the content-coding to respond from the `offered` ones
+    ///
+    /// this evaluates the `Accept-Encoding` header by RFC 7231, the coding
+    /// with the highest quality wins and a tie goes to the one listed first
+    /// in `offered`. `identity` is acceptable unless it's excluded by
+    /// `identity;q=0` or `*;q=0`, include it in `offered` if the body can
+    /// be sent uncompressed. `None` means nothing is acceptable and the
+    /// handler should respond `406 Not Acceptable`
+    pub fn acceptable_encoding<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        if !self.headers().contains_key(ACCEPT_ENCODING) {
+            // any coding is acceptable
+            return offered.first().cloned();
+        }
+        let accepted = self.quality_list(ACCEPT_ENCODING);
+        let quality = |coding: &str| {
+            let find = |name: &str| {
+                accepted
+                    .iter()
+                    .find(|&&(c, _)| c.eq_ignore_ascii_case(name))
+                    .map(|&(_, q)| q)
+            };
+            find(coding).or_else(|| find("*")).unwrap_or(
+                if coding.eq_ignore_ascii_case("identity") {
+                    1.0
+                } else {
+                    0.0
+                },
+            )
+        };
+        let mut best = None;
+        for &coding in offered {
+            let q = quality(coding);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((coding, q));
+            }
+        }
+        best.map(|(coding, _)| coding)
+    }
+
+    /// choose the charset of a text response from the `offered` ones
+    ///
+    /// this evaluates the `Accept-Charset` header with the quality values,
+    /// a tie goes to the one listed first in `offered`. without the header
+    /// any charset is acceptable and `utf-8` is preferred if it's offered.
+    /// a charset not listed is only acceptable by a `*`. `None` means
+    /// nothing is acceptable and the handler may respond `406 Not Acceptable`
+    pub fn preferred_charset<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        if !self.headers().contains_key(ACCEPT_CHARSET) {
+            return offered
+                .iter()
+                .find(|c| c.eq_ignore_ascii_case("utf-8"))
+                .or_else(|| offered.first())
+                .cloned();
+        }
+        let accepted = self.quality_list(ACCEPT_CHARSET);
+        let quality = |charset: &str| {
+            let find = |name: &str| {
+                accepted
+                    .iter()
+                    .find(|&&(c, _)| c.eq_ignore_ascii_case(name))
+                    .map(|&(_, q)| q)
+            };
+            find(charset).or_else(|| find("*")).unwrap_or(0.0)
+        };
+        let mut best = None;
+        for &charset in offered {
+            let q = quality(charset);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((charset, q));
+            }
+        }
+        best.map(|(charset, _)| charset)
+    }
+
+    /// check if the client accepts the trailer headers in a chunked response
+    pub fn accepts_trailers(&self) -> bool {
+        self.te()
+            .iter()
+            .any(|&(coding, _)| coding.eq_ignore_ascii_case("trailers"))
+    }
+
+    /// check if an informational `103 Early Hints` can be sent to the client
+    ///
+    /// the 1xx responses are not defined before HTTP/1.1, a HTTP/1.0 client
+    /// would take it as the final response
+    pub fn supports_early_hints(&self) -> bool {
+        !matches!(self.version(), Version::HTTP_09 | Version::HTTP_10)
+    }
+
+    /// return the scheme and the credentials of the `Authorization` header
+    ///
+    /// e.g. `("Negotiate", "YIIGhgYJ...")`, the credentials are passed
+    /// through as they are, so the application can run the handshake of
+    /// the scheme like SPNEGO. the scheme is case-insensitive and the
+    /// credentials are empty if absent
+    pub fn authorization(&self) -> Option<(&str, &str)> {
+        let value = self.headers().get(AUTHORIZATION)?.to_str().ok()?.trim();
+        let mut parts = value.splitn(2, [' ', '\t']);
+        let scheme = parts.next().filter(|s| !s.is_empty())?;
+        let credentials = parts.next().unwrap_or("").trim();
+        Some((scheme, credentials))
+    }
+
+    /// return the raw value of the cookie with the given name
+    ///
+    /// surrounding double quotes of the value are removed
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .get_all(COOKIE)
+            .into_iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| {
+                let mut kv = pair.splitn(2, '=');
+                let key = kv.next()?.trim();
+                let value = kv.next()?.trim();
+                if key == name {
+                    Some(value)
+                } else {
+                    None
+                }
+            })
+            .map(|v| {
+                if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') {
+                    &v[1..v.len() - 1]
+                } else {
+                    v
+                }
+            })
+            .next()
+    }
+
+    /// return the percent decoded value of the cookie with the given name
+    ///
+    /// return `None` if the cookie is not found or the encoding is malformed
+    pub fn cookie_decoded(&self, name: &str) -> Option<String> {
+        self.cookie(name).and_then(::percent::percent_decode_str)
+    }
+
+    /// read the body while copying at most `limit` bytes of it into `sink`
+    ///
+    /// this is useful to log the request body, read the body from the
+    /// returned reader, and get the sink back with `into_inner`
+    #[inline]
+    pub fn tee_body<W: Write>(&mut self, sink: W, limit: usize) -> TeeReader<&mut Self, W> {
+        TeeReader::new(self, sink, limit)
+    }
+
+    /// iterate the body in chunks of at most `size` bytes
+    ///
+    /// the body is only read when the next chunk is pulled, so a slow
+    /// consumer naturally holds back the client. the max body size is
+    /// respected, the iteration ends after an error
+    pub fn body_chunks(&mut self, size: usize) -> BodyChunks<'_> {
+        BodyChunks {
+            req: self,
+            size: size.max(1),
+            done: false,
+        }
+    }
+
+    /// read the next chunk of a chunked body as a whole
+    ///
+    /// the chunk boundaries are kept as sent by the client, this is for the
+    /// protocols that frame their messages with the chunks. a body that is
+    /// not chunked is returned as a single chunk. `None` at the end of the
+    /// body, the max body size is respected. don't mix it with `read` in the
+    /// middle of a chunk, the rest of that chunk is returned then
+    pub fn read_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        // the size line is read through the body reader, which sends the
+        // `100 Continue` and checks the max body size
+        let size = self.body_mut().chunk_size()?;
+        let mut buf = Vec::with_capacity(size.unwrap_or(0).min(64 * 1024));
+        match size {
+            Some(0) => return Ok(None),
+            Some(size) => {
+                self.by_ref().take(size as u64).read_to_end(&mut buf)?;
+                if buf.len() < size {
+                    let msg = format!("early eof, {} chunk bytes are missing", size - buf.len());
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+                }
+            }
+            None => {
+                self.read_to_end(&mut buf)?;
+                if buf.is_empty() {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(Bytes::from(buf)))
+    }
+
+    // set the client address, by the server
+    pub(crate) fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.remote_addr = addr;
+    }
+
+    /// return the address of the client
+    ///
+    /// it's the peer of the connection, or the address in the PROXY
+    /// protocol header if `HttpServer::set_proxy_protocol` is enabled.
+    /// `None` if it's unknown
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    // trust the proxy headers or not, set by the server
+    pub(crate) fn set_trust_proxy(&mut self, trust: bool) {
+        self.trust_proxy = trust;
+    }
+
+    // return the first value of the proxy header if the proxy is trusted
+    fn forwarded_header(&self, name: &str) -> Option<&str> {
+        if !self.trust_proxy {
+            return None;
+        }
+        self.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// return the scheme that the client used
+    ///
+    /// the `X-Forwarded-Proto` header is used if the proxy is trusted,
+    /// then the scheme of an absolute form target, otherwise `http`
+    pub fn scheme(&self) -> &str {
+        if let Some(proto) = self.forwarded_header("x-forwarded-proto") {
+            return proto;
+        }
+        match self.uri().scheme_part() {
+            Some(scheme) => scheme.as_str(),
+            None => "http",
+        }
+    }
+
+    /// check if the client connected over https, see `scheme`
+    pub fn is_secure(&self) -> bool {
+        self.scheme().eq_ignore_ascii_case("https")
+    }
+
+    /// return the host that the client used
+    ///
+    /// the `X-Forwarded-Host` header is used if the proxy is trusted,
+    /// then the authority of an absolute form target, otherwise the `Host` header
+    pub fn host(&self) -> Option<&str> {
+        if let Some(host) = self.forwarded_header("x-forwarded-host") {
+            return Some(host);
+        }
+        if let Some(authority) = self.uri().authority_part() {
+            return Some(authority.as_str());
+        }
+        self.headers().get(HOST).and_then(|v| v.to_str().ok())
+    }
+
+    /// build an absolute url on the same scheme and host for the path
+    ///
+    /// the path is percent encoded as needed, this is useful for the
+    /// `Location` header. `localhost` is used if the host is unknown
+    pub fn self_url(&self, path: &str) -> String {
+        let host = self.host().unwrap_or("localhost");
+        let slash = if path.starts_with('/') { "" } else { "/" };
+        let path = ::percent::percent_encode(path.as_bytes(), ::percent::is_url_char);
+        format!("{}://{}{}{}", self.scheme(), host, slash, path)
+    }
+
+    /// deserialize the query string into `T`
+    ///
+    /// e.g. `?page=2&limit=10` into a struct with the `page` and `limit`
+    /// fields, a missing query is taken as empty. a missing field or a
+    /// value of the wrong type is an `InvalidData` error that tells which
+    /// field is wrong
+    #[cfg(feature = "typed-query")]
+    pub fn query_typed<T: DeserializeOwned>(&self) -> io::Result<T> {
+        let query = self.uri().query().unwrap_or("");
+        ::serde_urlencoded::from_str(query).map_err(|e| {
+            let msg = format!("invalid query: {}", e);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })
+    }
+
+    /// read the body with the `Content-Encoding` decoded
+    ///
+    /// the transfer coding like `chunked` is already removed by the
+    /// request reader, this adds the content codings on top of it, in the
+    /// reverse order they are listed. `gzip`, `x-gzip`, `deflate` and
+    /// `identity` are supported, any other coding is an `InvalidData`
+    /// error before anything is read, it should be responded with `415`
+    #[cfg(feature = "gzip")]
+    pub fn decoded_body<'a>(&'a mut self) -> io::Result<Box<Read + 'a>> {
+        let mut codings = Vec::new();
+        for value in self.headers().get_all(CONTENT_ENCODING) {
+            let value = value.to_str().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid content-encoding")
+            })?;
+            for coding in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                match coding.to_ascii_lowercase().as_str() {
+                    "identity" => {}
+                    "gzip" | "x-gzip" => codings.push(true),
+                    "deflate" => codings.push(false),
+                    _ => {
+                        let msg = format!("unsupported content-encoding {:?}", coding);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                    }
+                }
+            }
+        }
+        let mut reader: Box<Read + 'a> = Box::new(self);
+        for gzip in codings.into_iter().rev() {
+            reader = if gzip {
+                Box::new(GzDecoder::new(reader))
+            } else {
+                Box::new(ZlibDecoder::new(reader))
+            };
+        }
+        Ok(reader)
+    }
+
+    /// return the value of the `Max-Forwards` header
+    ///
+    /// it's only meaningful for `TRACE` and `OPTIONS`, `None` if the
+    /// header is missing or not a number
+    pub fn max_forwards(&self) -> Option<u32> {
+        let n = self.header_u64(MAX_FORWARDS)?;
+        if n <= u64::from(u32::MAX) {
+            Some(n as u32)
+        } else {
+            None
+        }
+    }
+
+    /// decrement the `Max-Forwards` header before forwarding the request
+    ///
+    /// return false if it's already zero, the request must not be
+    /// forwarded and the server should respond itself. a request
+    /// without the header can always be forwarded
+    pub fn decrement_max_forwards(&mut self) -> bool {
+        match self.max_forwards() {
+            Some(0) => false,
+            Some(n) => {
+                let value = HeaderValue::from(n - 1);
+                self.headers_mut().insert(MAX_FORWARDS, value);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// return the unit of the `Range` header, e.g. `bytes`
+    ///
+    /// only the `bytes` unit is understood by `Response::serve_file`,
+    /// ranges of other units are ignored and the full content is served
+    pub fn range_unit(&self) -> Option<&str> {
+        let value = self.headers().get(RANGE)?.to_str().ok()?;
+        let mut parts = value.splitn(2, '=');
+        let unit = parts.next()?.trim();
+        parts.next()?;
+        if unit.is_empty() {
+            None
+        } else {
+            Some(unit)
+        }
+    }
+
+    /// check if the origin of the request is in the allowlist
+    ///
+    /// the `Origin` header is used, or the origin of the `Referer` if it's
+    /// absent. the origins are like `https://example.com:8443` and compared
+    /// case-insensitively. a missing or `null` origin is rejected, see
+    /// `origin_matches_with` to accept them
+    pub fn origin_matches(&self, allowed: &[&str]) -> bool {
+        self.origin_matches_with(allowed, OriginPolicy::default())
+    }
+
+    /// same as `origin_matches` with the policy for a missing or `null` origin
+    pub fn origin_matches_with(&self, allowed: &[&str], policy: OriginPolicy) -> bool {
+        let origin = match self.headers().get(ORIGIN) {
+            Some(v) => v.to_str().ok().map(|o| o.trim()),
+            None => self
+                .headers()
+                .get(REFERER)
+                .and_then(|v| v.to_str().ok())
+                .map(|r| url_origin(r.trim())),
+        };
+        match origin {
+            None | Some("") => policy.allow_missing,
+            Some("null") => policy.allow_null,
+            Some(o) => allowed
+                .iter()
+                .any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(o)),
+        }
+    }
+
+    /// check if the request is sent by `XMLHttpRequest`
+    ///
+    /// this checks the `X-Requested-With: XMLHttpRequest` header that is set
+    /// by most javascript libraries, useful to respond json instead of html
+    pub fn is_ajax(&self) -> bool {
+        self.headers()
+            .get("x-requested-with")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"XMLHttpRequest"))
+    }
+
+    /// check if the client asks for the reduced data usage by `Save-Data: on`
+    ///
+    /// a lighter response can be sent, e.g. with the smaller images
+    pub fn save_data(&self) -> bool {
+        self.headers().get("save-data").is_some_and(|v| {
+            let token = v.as_bytes().split(|&b| b == b';').next().unwrap_or(b"");
+            trim_ows(token).eq_ignore_ascii_case(b"on")
+        })
+    }
+
+    /// return the device memory in GiB from the `Device-Memory` client hint
+    pub fn device_memory(&self) -> Option<f32> {
+        self.client_hint("sec-ch-device-memory", "device-memory")
+            .and_then(|v| v.parse().ok())
+            .filter(|&m: &f32| m.is_finite() && m > 0.0)
+    }
+
+    /// return the device pixel ratio from the `DPR` client hint
+    pub fn dpr(&self) -> Option<f32> {
+        self.client_hint("sec-ch-dpr", "dpr")
+            .and_then(|v| v.parse().ok())
+            .filter(|&r: &f32| r.is_finite() && r > 0.0)
+    }
+
+    /// return the layout viewport width in css pixels from the
+    /// `Viewport-Width` client hint
+    pub fn viewport_width(&self) -> Option<u32> {
+        self.client_hint("sec-ch-viewport-width", "viewport-width")
+            .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|v| v.parse().ok())
+    }
+
+    // the value of a client hint, the `Sec-CH-` name is preferred
+    fn client_hint(&self, name: &str, legacy: &str) -> Option<&str> {
+        self.headers()
+            .get(name)
+            .or_else(|| self.headers().get(legacy))
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim())
+    }
+
+    // copy the request head without the body, used to render the error page
+    pub(crate) fn clone_head(&self) -> Request {
+        let mut raw_req = http::Request::new(BodyReader::EmptyReader);
+        *raw_req.method_mut() = self.method().clone();
+        *raw_req.uri_mut() = self.uri().clone();
+        *raw_req.version_mut() = self.version();
+        *raw_req.headers_mut() = self.headers().clone();
+        Request {
+            raw_req,
+            data: self.data.clone(),
+            trust_proxy: self.trust_proxy,
+            folded: self.folded,
+            bare_lf: self.bare_lf,
+            on_continue: None,
+            body_limit: None,
+            body_left: None,
+            remote_addr: self.remote_addr,
+        }
+    }
+
+    /// return the original request line bytes, e.g. `GET /path HTTP/1.1`
+    ///
+    /// the line terminator is not included
+    pub fn request_line(&self) -> &[u8] {
+        let line = &self.data[..];
+        let end = line.iter().position(|&b| b == b'\n').unwrap_or(line.len());
+        let line = &line[..end];
+        match line.last() {
+            Some(&b'\r') => &line[..end - 1],
+            _ => line,
+        }
+    }
+
+    /// return the request target exactly as it was on the request line
+    ///
+    /// e.g. `/a/b?x=1%20`, the path and query are not decoded or
+    /// normalized, so a proxy can forward it unchanged
+    pub fn target(&self) -> &str {
+        use std::str;
+
+        let target = self
+            .request_line()
+            .split(|&b| b == b' ')
+            .filter(|t| !t.is_empty())
+            .nth(1)
+            .unwrap_or(b"");
+        // the parser only accepts the visible ascii characters
+        str::from_utf8(target).unwrap_or("")
+    }
+
+    /// iterate the header names in the order they are sent
+    ///
+    /// the names keep the original casing and a repeated header is given
+    /// once for each line, use `headers().keys()` for the distinct names
+    pub fn header_names(&self) -> impl Iterator<Item = &str> {
+        // the parser only accepts the token characters in a name
+        self.raw_headers()
+            .map(|(name, _)| str::from_utf8(name).unwrap_or(""))
+    }
+
+    /// iterate the header `(name, value)` pairs as the exact bytes sent
+    ///
+    /// unlike `headers()` the names keep the original casing, repeated
+    /// headers are kept in order and the values are not converted, this is
+    /// needed to verify the signatures over the canonical headers.
+    /// a folded header is given as unfolded
+    pub fn raw_headers(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut r = httparse::Request::new(&mut headers);
+        // the head is already parsed once, it can't fail
+        let mut raw = Vec::new();
+        if r.parse(&self.data).is_ok() {
+            raw.extend(r.headers.iter().map(|h| (h.name.as_bytes(), h.value)));
+        }
+        raw.into_iter()
+    }
+
+    /// return the remaining body size if it's known
+    ///
+    /// this is useful to pre-allocate the buffer before `read_to_end`
+    /// a chunked body would return `None`
+    #[inline]
+    pub fn body_size_hint(&self) -> Option<usize> {
+        self.body().size_hint()
+    }
+
+    /// return true if the whole body is read by the handler
+    ///
+    /// a request without body is always consumed
+    #[inline]
+    pub fn body_consumed(&self) -> bool {
+        self.body().size_hint() == Some(0)
+    }
 }
 
 impl Deref for Request {
@@ -103,7 +1142,7 @@ impl Deref for Request {
     /// deref to the http::Request
     #[inline]
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.raw_req
     }
 }
 
@@ -111,7 +1150,7 @@ impl DerefMut for Request {
     /// deref_mut to the http::Request
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.raw_req
     }
 }
 
@@ -122,8 +1161,766 @@ impl Read for Request {
     }
 }
 
+// the deferred `100 Continue`, it's taken when it's sent
+type ContinueHook = Rc<RefCell<Option<Box<FnMut() -> io::Result<()>>>>>;
+
+// a reader that sends the deferred `100 Continue` before the first read
+struct ContinueReader {
+    inner: Rc<RefCell<Read>>,
+    hook: ContinueHook,
+}
+
+impl Read for ContinueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // the client is waiting for the `100 Continue` to send the body
+        let f = self.hook.borrow_mut().take();
+        if let Some(mut f) = f {
+            f()?;
+        }
+        self.inner.borrow_mut().read(buf)
+    }
+}
+
+// a reader that fails once more than `remain` bytes are read
+struct LimitReader {
+    inner: Rc<RefCell<Read>>,
+    remain: usize,
+    exceeded: Rc<Cell<bool>>,
+}
+
+impl Read for LimitReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::cmp;
+
+        if !self.exceeded.get() {
+            // one more byte than the limit tells if the body is too large
+            let len = cmp::min(buf.len(), self.remain.saturating_add(1));
+            let n = self.inner.borrow_mut().read(&mut buf[..len])?;
+            if n <= self.remain {
+                self.remain -= n;
+                return Ok(n);
+            }
+            self.exceeded.set(true);
+        }
+        let msg = "request body too large";
+        Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        // the body is not sent before the `100 Continue`, and the rest of a
+        // too large body is not wanted, so they are never drained
+        let pending = match self.on_continue {
+            Some(ref hook) => hook.borrow().is_some(),
+            None => false,
+        };
+        let exceeded = match self.body_limit {
+            Some((_, ref exceeded)) => exceeded.get(),
+            None => false,
+        };
+        let drainable = !(pending || exceeded);
+        match self.body_left {
+            // the server drains the rest after the response is sent
+            Some(ref left) => left.set(self.body().size_hint().filter(|_| drainable)),
+            // otherwise it's drained by the body reader
+            None if drainable => return,
+            None => {}
+        }
+        self.body_mut().discard();
+    }
+}
+
+/// an iterator of the request body chunks
+///
+/// created by `Request::body_chunks`
+pub struct BodyChunks<'a> {
+    req: &'a mut Request,
+    size: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for BodyChunks<'a> {
+    type Item = io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buf = vec![0; self.size];
+        match self.req.read(&mut buf) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(n) => {
+                buf.truncate(n);
+                Some(Ok(Bytes::from(buf)))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 impl fmt::Debug for Request {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<HTTP Request {} {}>", self.method(), self.uri())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(raw: &[u8]) -> Request {
+        let mut buf = BytesMut::from(raw);
+        let mut req = decode(&mut buf, &mut 0).unwrap().unwrap();
+        let body: Rc<RefCell<Read>> = Rc::new(RefCell::new(Cursor::new(buf.to_vec())));
+        req.set_reader(body);
+        req
+    }
+
+    #[test]
+    fn test_body_size_hint() {
+        let mut req = parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(req.body_size_hint(), Some(5));
+        let mut s = String::new();
+        req.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+        assert_eq!(req.body_size_hint(), Some(0));
+
+        let req = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        assert_eq!(req.body_size_hint(), None);
+
+        let req = parse(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(req.body_size_hint(), Some(0));
+
+        // a request body is never delimited by closing the connection
+        let mut req = parse(b"POST / HTTP/1.0\r\nConnection: close\r\n\r\nhello");
+        assert_eq!(req.body_size_hint(), Some(0));
+        let mut s = String::new();
+        req.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "");
+    }
+
+    #[test]
+    fn test_connection_tokens() {
+        let req = parse(b"GET / HTTP/1.1\r\nConnection: Keep-Alive, Upgrade\r\n\r\n");
+        let tokens: Vec<_> = req.connection_tokens().collect();
+        assert_eq!(tokens, ["keep-alive", "upgrade"]);
+        assert!(req.has_connection_token("upgrade"));
+        assert!(req.has_connection_token("Keep-Alive"));
+        assert!(!req.has_connection_token("close"));
+        assert!(::server::should_keep_alive(Version::HTTP_10, req.headers()));
+    }
+
+    #[test]
+    fn test_cookie() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nCookie: id=42; data=%7B%22a%22%3A1%7D\r\n\
+              Cookie: quoted=\"a%20b\"; bad=%zz\r\n\r\n",
+        );
+        assert_eq!(req.cookie("id"), Some("42"));
+        assert_eq!(req.cookie("data"), Some("%7B%22a%22%3A1%7D"));
+        assert_eq!(req.cookie_decoded("data"), Some("{\"a\":1}".to_owned()));
+        assert_eq!(req.cookie_decoded("quoted"), Some("a b".to_owned()));
+        assert_eq!(req.cookie("bad"), Some("%zz"));
+        assert_eq!(req.cookie_decoded("bad"), None);
+        assert_eq!(req.cookie("missing"), None);
+    }
+
+    #[test]
+    fn test_tee_body() {
+        let mut req = parse(b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+        let mut log = Vec::new();
+        let mut s = String::new();
+        req.tee_body(&mut log, 1024).read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello world");
+        assert_eq!(log, s.as_bytes());
+    }
+
+    #[test]
+    fn test_identity_transfer_encoding() {
+        let mut req = parse(
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: identity\r\n\
+              Content-Length: 5\r\n\r\nhello",
+        );
+        assert!(req.validate().is_ok());
+        let mut s = String::new();
+        req.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+
+        // without the length there is no body
+        let req = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: Identity\r\n\r\n");
+        assert_eq!(req.body_size_hint(), Some(0));
+        let req = parse(
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+              Content-Length: 5\r\n\r\n",
+        );
+        assert!(req.validate().is_err());
+    }
+
+    #[test]
+    fn test_body_chunks() {
+        let mut req = parse(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n5\r\ndefgh\r\n0\r\n\r\n",
+        );
+        let chunks = req.body_chunks(4).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(chunks, vec![&b"abc"[..], &b"defg"[..], &b"h"[..]]);
+        assert!(req.body_consumed());
+
+        let mut buf = BytesMut::from(&b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nabcdef"[..]);
+        let mut req = decode(&mut buf, &mut 0).unwrap().unwrap();
+        req.set_body_limit(4, Rc::new(Cell::new(false)));
+        let body: Rc<RefCell<Read>> = Rc::new(RefCell::new(Cursor::new(buf.to_vec())));
+        req.set_reader(body);
+        let mut chunks = req.body_chunks(4);
+        assert!(chunks.next().unwrap().is_err());
+        assert!(chunks.next().is_none());
+    }
+
+    #[test]
+    fn test_read_chunk() {
+        let mut req = parse(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n1;ext=1\r\n \r\n5\r\nworld\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        );
+        assert_eq!(req.read_chunk().unwrap().unwrap(), "hello");
+        assert_eq!(req.read_chunk().unwrap().unwrap(), " ");
+        assert_eq!(req.read_chunk().unwrap().unwrap(), "world");
+        assert!(req.read_chunk().unwrap().is_none());
+        assert!(req.read_chunk().unwrap().is_none());
+        assert!(req.body_consumed());
+
+        // the chunk is cut short
+        let mut req = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel");
+        assert!(req.read_chunk().is_err());
+
+        let mut req = parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(req.read_chunk().unwrap().unwrap(), "hello");
+        assert!(req.read_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_self_url() {
+        let raw = b"GET / HTTP/1.1\r\nHost: backend:8080\r\nX-Forwarded-Proto: https\r\n\
+                    X-Forwarded-Host: example.com\r\n\r\n";
+        let mut req = parse(raw);
+        assert_eq!(req.self_url("/new"), "http://backend:8080/new");
+
+        req.set_trust_proxy(true);
+        assert_eq!(req.scheme(), "https");
+        assert_eq!(req.host(), Some("example.com"));
+        assert_eq!(req.self_url("/new"), "https://example.com/new");
+        assert_eq!(
+            req.self_url("a b/ü?x=1"),
+            "https://example.com/a%20b/%C3%BC?x=1"
+        );
+    }
+
+    #[test]
+    fn test_range_unit() {
+        let req = parse(b"GET / HTTP/1.1\r\nRange: items=0-10\r\n\r\n");
+        assert_eq!(req.range_unit(), Some("items"));
+        let req = parse(b"GET / HTTP/1.1\r\nRange: bytes=0-\r\n\r\n");
+        assert_eq!(req.range_unit(), Some("bytes"));
+        let req = parse(b"GET / HTTP/1.1\r\nRange: 0-10\r\n\r\n");
+        assert_eq!(req.range_unit(), None);
+        let req = parse(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(req.range_unit(), None);
+    }
+
+    #[test]
+    fn test_is_ajax() {
+        let req = parse(b"GET / HTTP/1.1\r\nX-Requested-With: XMLHttpRequest\r\n\r\n");
+        assert!(req.is_ajax());
+        let req = parse(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!req.is_ajax());
+    }
+
+    #[test]
+    fn test_client_hints() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nSave-Data: on\r\nDevice-Memory: 0.5\r\n\
+              DPR: 2\r\nViewport-Width: 412\r\n\r\n",
+        );
+        assert!(req.save_data());
+        assert_eq!(req.device_memory(), Some(0.5));
+        assert_eq!(req.dpr(), Some(2.0));
+        assert_eq!(req.viewport_width(), Some(412));
+
+        let req = parse(
+            b"GET / HTTP/1.1\r\nSave-Data: ON; x=1\r\nDPR: 1\r\nSec-CH-DPR: 3.5\r\n\
+              Viewport-Width: -1\r\nDevice-Memory: NaN\r\n\r\n",
+        );
+        assert!(req.save_data());
+        assert_eq!(req.dpr(), Some(3.5));
+        assert_eq!(req.viewport_width(), None);
+        assert_eq!(req.device_memory(), None);
+
+        let req = parse(b"GET / HTTP/1.1\r\nSave-Data: off\r\n\r\n");
+        assert!(!req.save_data());
+        let req = parse(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!req.save_data());
+        assert_eq!(req.dpr(), None);
+    }
+
+    #[test]
+    fn test_header_names() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\
+              cookie: a=1\r\nCookie: b=2\r\nX-CUSTOM: x\r\n\r\n",
+        );
+        let names: Vec<_> = req.header_names().collect();
+        assert_eq!(names, ["Host", "Accept", "cookie", "Cookie", "X-CUSTOM"]);
+    }
+
+    #[test]
+    fn test_request_line() {
+        let req = parse(b"GET /path?a=1 HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(req.request_line(), &b"GET /path?a=1 HTTP/1.1"[..]);
+        let req = parse(b"\r\nPOST /x HTTP/1.0\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(req.request_line(), &b"POST /x HTTP/1.0"[..]);
+    }
+
+    #[test]
+    fn test_is_hop_by_hop() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, X-Trace\r\n\
+              X-Trace: 1\r\nAccept: */*\r\n\r\n",
+        );
+        assert!(req.is_hop_by_hop("Connection"));
+        assert!(req.is_hop_by_hop("transfer-encoding"));
+        assert!(req.is_hop_by_hop("TE"));
+        assert!(req.is_hop_by_hop("x-trace"));
+        assert!(!req.is_hop_by_hop("Accept"));
+        assert!(!req.is_hop_by_hop("Host"));
+    }
+
+    #[test]
+    fn test_leading_crlf() {
+        let req = parse(b"\r\n\r\nGET /a HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(req.uri().path(), "/a");
+
+        // only empty lines, wait for more data
+        let mut buf = BytesMut::from(&b"\r\n"[..]);
+        assert!(decode(&mut buf, &mut 0).unwrap().is_none());
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(vec![b'\n'; MAX_LEADING_CRLF + 1]);
+        assert!(decode(&mut buf, &mut 0).is_err());
+
+        // the limit is for the connection, not for each read
+        let mut skipped = 0;
+        for _ in 0..MAX_LEADING_CRLF {
+            let mut buf = BytesMut::from(&b"\n"[..]);
+            assert!(decode(&mut buf, &mut skipped).unwrap().is_none());
+        }
+        let mut buf = BytesMut::from(&b"\nGET / HTTP/1.1\r\n\r\n"[..]);
+        assert!(decode(&mut buf, &mut skipped).is_err());
+
+        // and it starts over after a request
+        let mut skipped = MAX_LEADING_CRLF;
+        let mut buf = BytesMut::with_capacity(64);
+        buf.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n\n");
+        assert!(decode(&mut buf, &mut skipped).unwrap().is_some());
+        assert!(decode(&mut buf, &mut skipped).unwrap().is_none());
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_duplicate_transfer_encoding() {
+        let decode_err = |raw: &[u8]| {
+            let mut buf = BytesMut::from(raw);
+            decode(&mut buf, &mut 0).unwrap_err().kind()
+        };
+        assert_eq!(
+            decode_err(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                  transfer-encoding: chunked\r\n\r\n"
+            ),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            decode_err(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n"
+            ),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            decode_err(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, Chunked\r\n\r\n"),
+            io::ErrorKind::InvalidData
+        );
+
+        let req = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n");
+        assert_eq!(req.headers()[TRANSFER_ENCODING], "gzip, chunked");
+    }
+
+    #[test]
+    fn test_h2_preface() {
+        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+        assert!(is_h2_preface(preface));
+        let mut buf = BytesMut::from(&preface[..]);
+        assert!(decode(&mut buf, &mut 0).is_err());
+        assert!(!is_h2_preface(b"PRI * HTTP/1.1\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_extension_method() {
+        let req = parse(b"PURGE /cache HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(req.method().as_str(), "PURGE");
+        assert_eq!(req.uri().path(), "/cache");
+
+        for raw in &[
+            &b"GE(T / HTTP/1.1\r\nHost: a\r\n\r\n"[..],
+            &b"G\xffT / HTTP/1.1\r\nHost: a\r\n\r\n"[..],
+        ] {
+            let mut buf = BytesMut::from(*raw);
+            assert!(decode(&mut buf, &mut 0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_obs_fold() {
+        let mut req = parse(
+            b"POST / HTTP/1.1\r\nHost: a\r\nX-Long: first\r\n \t second\r\n\
+              \tthird\r\nContent-Length: 2\r\n\r\nok",
+        );
+        assert_eq!(req.headers()["x-long"], "first second third");
+        assert!(req.validate().is_err());
+        let mut body = String::new();
+        req.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "ok");
+
+        let req = parse(b"GET / HTTP/1.1\r\nHost: a\r\nX-Short: v\r\n\r\n");
+        assert!(req.validate().is_ok());
+
+        // the folding is kept until the rest of the head is received
+        let mut buf = BytesMut::from(&b"GET / HTTP/1.1\r\nHost: a\r\nX: a\r\n b\r\n"[..]);
+        assert!(decode(&mut buf, &mut 0).unwrap().is_none());
+        buf.extend_from_slice(b"\r\n");
+        let req = decode(&mut buf, &mut 0).unwrap().unwrap();
+        assert_eq!(req.headers()["x"], "a b");
+        assert!(req.validate().is_err());
+    }
+
+    #[test]
+    fn test_bare_lf() {
+        let mut req = parse(b"POST /a HTTP/1.1\nHost: a\r\nContent-Length: 2\n\nok");
+        assert_eq!(req.uri().path(), "/a");
+        assert_eq!(req.headers()[HOST], "a");
+        assert!(req.validate().is_err());
+        let mut body = String::new();
+        req.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "ok");
+
+        // the empty lines before the request line are not a part of the head
+        let req = parse(b"\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(req.validate().is_ok());
+    }
+
+    #[test]
+    fn test_preferred_charset() {
+        let charset = |raw: &[u8], offered: &[&'static str]| parse(raw).preferred_charset(offered);
+        let raw = b"GET / HTTP/1.1\r\nAccept-Charset: utf-8, iso-8859-1;q=0.5\r\n\r\n";
+        assert_eq!(charset(raw, &["iso-8859-1", "utf-8"]), Some("utf-8"));
+        assert_eq!(
+            charset(raw, &["ISO-8859-1", "shift_jis"]),
+            Some("ISO-8859-1")
+        );
+        assert_eq!(charset(raw, &["shift_jis"]), None);
+
+        let raw = b"GET / HTTP/1.1\r\nAccept-Charset: *;q=0.3, utf-8;q=0\r\n\r\n";
+        assert_eq!(charset(raw, &["utf-8", "iso-8859-1"]), Some("iso-8859-1"));
+        assert_eq!(charset(raw, &["utf-8"]), None);
+
+        let raw = b"GET / HTTP/1.1\r\n\r\n";
+        assert_eq!(charset(raw, &["iso-8859-1", "UTF-8"]), Some("UTF-8"));
+        assert_eq!(charset(raw, &["iso-8859-1"]), Some("iso-8859-1"));
+        assert_eq!(charset(raw, &[]), None);
+    }
+
+    #[test]
+    fn test_acceptable_encoding() {
+        let offered = ["br", "gzip", "identity"];
+        let encoding = |raw: &[u8]| parse(raw).acceptable_encoding(&offered);
+
+        assert_eq!(encoding(b"GET / HTTP/1.1\r\n\r\n"), Some("br"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: gzip, br;q=0.8\r\n\r\n";
+        assert_eq!(encoding(raw), Some("gzip"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: GZIP, BR\r\n\r\n";
+        assert_eq!(encoding(raw), Some("br"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: deflate\r\n\r\n";
+        assert_eq!(encoding(raw), Some("identity"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: \r\n\r\n";
+        assert_eq!(encoding(raw), Some("identity"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: *;q=0.5, gzip;q=0\r\n\r\n";
+        assert_eq!(encoding(raw), Some("br"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: gzip, *;q=0\r\n\r\n";
+        assert_eq!(encoding(raw), Some("gzip"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: identity, *;q=0\r\n\r\n";
+        assert_eq!(encoding(raw), Some("identity"));
+        let raw = b"GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0, identity;q=0\r\n\r\n";
+        assert_eq!(encoding(raw), None);
+    }
+
+    #[test]
+    fn test_raw_headers() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nX-Amz-Date: 20180101T000000Z\r\n\
+              x-meta:  a\xffb\r\nX-Meta: c\r\n\r\n",
+        );
+        let raw = req.raw_headers().collect::<Vec<_>>();
+        assert_eq!(
+            raw,
+            vec![
+                (&b"X-Amz-Date"[..], &b"20180101T000000Z"[..]),
+                (&b"x-meta"[..], &b"a\xffb"[..]),
+                (&b"X-Meta"[..], &b"c"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_length() {
+        let req = parse(b"POST / HTTP/1.1\r\nContent-Length: 0012\r\n\r\n");
+        assert_eq!(req.content_length().unwrap(), Some(12));
+        let req = parse(b"POST / HTTP/1.1\r\n\r\n");
+        assert_eq!(req.content_length().unwrap(), None);
+
+        for value in &[
+            "12abc",
+            "+5",
+            "-1",
+            "0x10",
+            "1 2",
+            "99999999999999999999999",
+        ] {
+            let raw = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", value);
+            let req = parse(raw.as_bytes());
+            let err = req.content_length().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(req.body_size_hint(), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_header_u64() {
+        let req = parse(
+            b"GET / HTTP/1.1\r\nAge: 120\r\nX-Count: 007\r\nX-Neg: -1\r\n\
+              X-Hex: 0x10\r\nX-Big: 18446744073709551616\r\nX-Empty:\r\n\r\n",
+        );
+        assert_eq!(req.header_u64(AGE), Some(120));
+        assert_eq!(req.header_u64("x-count"), Some(7));
+        assert_eq!(req.header_u64("x-neg"), None);
+        assert_eq!(req.header_u64("x-hex"), None);
+        assert_eq!(req.header_u64("x-big"), None);
+        assert_eq!(req.header_u64("x-empty"), None);
+        assert_eq!(req.header_u64("x-missing"), None);
+    }
+
+    #[test]
+    fn test_origin_matches() {
+        let allowed = ["https://example.com", "https://app.example.com:8443/"];
+        let req = parse(b"POST / HTTP/1.1\r\nOrigin: https://App.Example.com:8443\r\n\r\n");
+        assert!(req.origin_matches(&allowed));
+        let req = parse(b"POST / HTTP/1.1\r\nOrigin: https://evil.com\r\n\r\n");
+        assert!(!req.origin_matches(&allowed));
+        // the referer is the fallback
+        let req = parse(b"POST / HTTP/1.1\r\nReferer: https://example.com/a?b\r\n\r\n");
+        assert!(req.origin_matches(&allowed));
+        let req = parse(b"POST / HTTP/1.1\r\nReferer: https://example.com.evil.com/\r\n\r\n");
+        assert!(!req.origin_matches(&allowed));
+
+        let lax = OriginPolicy {
+            allow_missing: true,
+            allow_null: false,
+        };
+        let req = parse(b"POST / HTTP/1.1\r\n\r\n");
+        assert!(!req.origin_matches(&allowed));
+        assert!(req.origin_matches_with(&allowed, lax));
+        let req = parse(b"POST / HTTP/1.1\r\nOrigin: null\r\n\r\n");
+        assert!(!req.origin_matches_with(&allowed, lax));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decoded_body() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+
+        let text = "hello gzip world, ".repeat(100);
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(text.as_bytes()).unwrap();
+        let gz = gz.finish().unwrap();
+
+        // chunked on the wire, the chunks split the gzip stream
+        let mut raw = b"POST / HTTP/1.1\r\nContent-Encoding: gzip\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n"
+            .to_vec();
+        for chunk in gz.chunks(7) {
+            raw.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            raw.extend_from_slice(chunk);
+            raw.extend_from_slice(b"\r\n");
+        }
+        raw.extend_from_slice(b"0\r\n\r\n");
+        let mut req = parse(&raw);
+        let mut body = String::new();
+        req.decoded_body()
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, text);
+
+        // deflate was applied first, then gzip
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"layered").unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&zlib.finish().unwrap()).unwrap();
+        let gz = gz.finish().unwrap();
+        let mut raw = format!(
+            "POST / HTTP/1.1\r\nContent-Encoding: deflate, gzip\r\nContent-Length: {}\r\n\r\n",
+            gz.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(&gz);
+        let mut req = parse(&raw);
+        let mut body = String::new();
+        req.decoded_body()
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "layered");
+
+        let mut req =
+            parse(b"POST / HTTP/1.1\r\nContent-Encoding: br\r\nContent-Length: 0\r\n\r\n");
+        let err = req.decoded_body().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_max_forwards() {
+        let mut req = parse(b"TRACE / HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n");
+        assert_eq!(req.max_forwards(), Some(0));
+        assert!(!req.decrement_max_forwards());
+
+        let mut req = parse(b"OPTIONS * HTTP/1.1\r\nMax-Forwards: 2\r\n\r\n");
+        assert!(req.decrement_max_forwards());
+        assert_eq!(req.max_forwards(), Some(1));
+
+        let mut req = parse(b"TRACE / HTTP/1.1\r\nMax-Forwards: x\r\n\r\n");
+        assert_eq!(req.max_forwards(), None);
+        assert!(req.decrement_max_forwards());
+    }
+
+    #[cfg(feature = "typed-query")]
+    #[test]
+    fn test_query_typed() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Page {
+            page: u32,
+            limit: u32,
+            sort: Option<String>,
+        }
+
+        let req = parse(b"GET /items?page=2&limit=10 HTTP/1.1\r\n\r\n");
+        let page: Page = req.query_typed().unwrap();
+        assert_eq!(
+            page,
+            Page {
+                page: 2,
+                limit: 10,
+                sort: None,
+            }
+        );
+
+        let req = parse(b"GET /items?page=2 HTTP/1.1\r\n\r\n");
+        let err = req.query_typed::<Page>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("limit"));
+        let req = parse(b"GET /items?page=two&limit=10 HTTP/1.1\r\n\r\n");
+        assert!(req.query_typed::<Page>().is_err());
+    }
+
+    #[test]
+    fn test_supports_early_hints() {
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").supports_early_hints());
+        assert!(!parse(b"GET / HTTP/1.0\r\n\r\n").supports_early_hints());
+    }
+
+    #[test]
+    fn test_te() {
+        let req = parse(b"GET / HTTP/1.1\r\nTE: trailers, deflate;q=0.5\r\n\r\n");
+        assert_eq!(req.te(), vec![("trailers", 1.0), ("deflate", 0.5)]);
+        assert!(req.accepts_trailers());
+
+        let req = parse(b"GET / HTTP/1.1\r\nTE: gzip; Q=0.2 ,\r\n\r\n");
+        assert_eq!(req.te(), vec![("gzip", 0.2)]);
+        assert!(!req.accepts_trailers());
+    }
+
+    #[test]
+    fn test_target() {
+        let req = parse(b"GET /a/b?x=1%20 HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(req.target(), "/a/b?x=1%20");
+        assert_eq!(req.uri().path(), "/a/b");
+        let req = parse(b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(req.target(), "*");
+    }
+
+    #[test]
+    fn test_date_header() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let req = parse(
+            b"GET / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+              If-Unmodified-Since: Sunday, 06-Nov-94 08:49:37 GMT\r\n\
+              Expires: not a date\r\n\r\n",
+        );
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(req.date_header(IF_MODIFIED_SINCE), Some(date));
+        assert_eq!(req.date_header(IF_UNMODIFIED_SINCE), Some(date));
+        assert_eq!(req.date_header(EXPIRES), None);
+        assert_eq!(req.date_header("date"), None);
+    }
+
+    #[test]
+    fn test_check_preconditions() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+        let later = date + Duration::from_secs(60);
+        let req =
+            parse(b"PUT / HTTP/1.1\r\nIf-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n");
+        assert_eq!(
+            req.check_preconditions(None, Some(later)),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(req.check_preconditions(None, Some(date)), None);
+
+        let req = parse(b"PUT / HTTP/1.1\r\nIf-Match: \"xyz\", \"abc\"\r\n\r\n");
+        assert_eq!(req.check_preconditions(Some("\"abc\""), Some(later)), None);
+        assert_eq!(
+            req.check_preconditions(Some("\"def\""), None),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+
+        let req = parse(b"PUT / HTTP/1.1\r\nIf-Match: W/\"abc\"\r\n\r\n");
+        assert_eq!(
+            req.check_preconditions(Some("\"abc\""), None),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        let req = parse(b"PUT / HTTP/1.1\r\nIf-Match: *\r\n\r\n");
+        assert_eq!(req.check_preconditions(Some("\"abc\""), None), None);
+        assert_eq!(
+            req.check_preconditions(None, None),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+    }
+}
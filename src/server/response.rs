@@ -4,13 +4,25 @@
 //! receiving a request.
 use std::cell::RefCell;
 use std::fmt;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
+use std::time::Duration;
+
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "gzip")]
+use flate2::Compression;
 
 use body::BodyWriter;
+use buffer::BufferIo;
 use http::header::*;
-use http::{self, StatusCode};
+use http::{self, Method, StatusCode};
+use server::{Request, SecureCookiePolicy};
+
+// the max body size that is buffered to compute the content-length for a
+// client that doesn't support the chunked encoding
+const MAX_DOWNGRADE_BUFFER: usize = 64 * 1024;
 
 /// The outgoing half for a Stream, created by a `Server` and given to a `HttpService`.
 ///
@@ -27,6 +39,32 @@ pub struct Response {
     writer: Rc<RefCell<Write>>,
     // the cached response size
     body_size: Option<usize>,
+    // the response is for a HEAD request, no body should be sent
+    head_only: bool,
+    // the body is buffered here before the head is written if reserved
+    buffer: Option<Vec<u8>>,
+    // the underlying connection, used to flush regardless of the flush mode
+    // and taken by the handler with `take_raw_stream`
+    raw_io: Option<Rc<RefCell<RawIo>>>,
+    // the client is HTTP/1.0
+    http10_client: bool,
+    // the body source that is streamed out when the response is dropped
+    body_reader: Option<(Box<Read>, Option<u64>)>,
+    // the charset appended to the text content type that has no charset
+    default_charset: Option<String>,
+    // the client accepts the gzip content coding
+    #[cfg(feature = "gzip")]
+    accepts_gzip: bool,
+    // the content type used if a body is sent without one
+    default_content_type: Option<String>,
+    // the reason phrase that overrides the canonical one
+    reason: Option<String>,
+    // the pre-serialized header lines written after the headers
+    raw_headers: Vec<u8>,
+    // the connection is taken, no body framing is used
+    detached: bool,
+    // how to handle the `Secure` cookies on a plaintext connection
+    insecure_cookies: SecureCookiePolicy,
 }
 
 impl fmt::Debug for Response {
@@ -43,6 +81,167 @@ impl Response {
             raw_rsp: http::Response::new(BodyWriter::InvalidWriter),
             writer: stream,
             body_size: None,
+            head_only: false,
+            buffer: None,
+            raw_io: None,
+            http10_client: false,
+            body_reader: None,
+            default_charset: None,
+            #[cfg(feature = "gzip")]
+            accepts_gzip: false,
+            default_content_type: None,
+            reason: None,
+            raw_headers: Vec::new(),
+            detached: false,
+            insecure_cookies: SecureCookiePolicy::Keep,
+        }
+    }
+
+    // mark the response as for a HEAD request
+    // the head is written as usual but the body is discarded
+    pub(crate) fn set_head_only(&mut self) {
+        self.head_only = true;
+    }
+
+    // set the underlying connection
+    pub(crate) fn set_raw_io(&mut self, io: Rc<RefCell<RawIo>>) {
+        self.raw_io = Some(io);
+    }
+
+    // the client is HTTP/1.0, no informational response is sent to it and
+    // the body is never sent chunked. a body of unknown size is buffered to
+    // compute the content-length, or delimited by closing the connection
+    // if it's too large
+    pub(crate) fn set_http10_client(&mut self) {
+        self.http10_client = true;
+    }
+
+    // the request negotiated gzip, the body reader of unknown size is compressed
+    #[cfg(feature = "gzip")]
+    pub(crate) fn set_accepts_gzip(&mut self) {
+        self.accepts_gzip = true;
+    }
+
+    // the connection is taken by the handler and can't be reused
+    pub(crate) fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    // set the charset that is appended to a text content type without charset
+    pub(crate) fn set_default_charset(&mut self, charset: &str) {
+        self.default_charset = Some(charset.to_owned());
+    }
+
+    // set the content type that is used if the handler sends a body without one
+    pub(crate) fn set_default_content_type(&mut self, content_type: &str) {
+        self.default_content_type = Some(content_type.to_owned());
+    }
+
+    // check if the body of unknown size should be buffered for the content-length
+    fn needs_downgrade(&self) -> bool {
+        self.http10_client
+            && self.body_size.is_none()
+            && !self.head_only
+            && !self.detached
+            && status_has_body(self.status())
+    }
+
+    // write the head and the buffered body, the rest is close-delimited
+    fn spill_buffer(&mut self) -> io::Result<()> {
+        if let Some(buf) = self.buffer.take() {
+            *self.body_mut() = self.write_head()?;
+            self.body_mut().write_all(&buf)?;
+        }
+        Ok(())
+    }
+
+    // set the policy of the `Secure` cookies, only for a plaintext connection
+    pub(crate) fn set_insecure_cookie_policy(&mut self, policy: SecureCookiePolicy) {
+        self.insecure_cookies = policy;
+    }
+
+    // strip the `Secure` flag or drop the cookie per the policy
+    fn apply_cookie_policy(&mut self) {
+        let strip = match self.insecure_cookies {
+            SecureCookiePolicy::Keep => return,
+            SecureCookiePolicy::Strip => true,
+            SecureCookiePolicy::Reject => false,
+        };
+        if !self
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .any(|v| is_secure_cookie(v.as_bytes()))
+        {
+            return;
+        }
+        let cookies = self
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        self.headers_mut().remove(SET_COOKIE);
+        for cookie in cookies {
+            let cookie = if !is_secure_cookie(cookie.as_bytes()) {
+                cookie
+            } else if strip {
+                let stripped = cookie
+                    .as_bytes()
+                    .split(|&b| b == b';')
+                    .enumerate()
+                    .filter(|&(i, attr)| {
+                        i == 0 || !trim_bytes(attr).eq_ignore_ascii_case(b"secure")
+                    })
+                    .map(|(_, attr)| attr)
+                    .collect::<Vec<_>>()
+                    .join(&b';');
+                match HeaderValue::from_bytes(&stripped) {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                }
+            } else {
+                info!("drop the secure cookie on a plaintext connection");
+                continue;
+            };
+            self.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+
+    // set the default content type if there is a body but no content type
+    fn apply_default_content_type(&mut self) {
+        let content_type = match self.default_content_type.take() {
+            Some(content_type) => content_type,
+            None => return,
+        };
+        if self.detached
+            || self.body_size == Some(0)
+            || !status_has_body(self.status())
+            || self.headers().contains_key(CONTENT_TYPE)
+        {
+            return;
+        }
+        if let Ok(value) = HeaderValue::from_str(&content_type) {
+            self.headers_mut().insert(CONTENT_TYPE, value);
+        }
+    }
+
+    // append the default charset to the `text/*` content type if needed
+    fn apply_default_charset(&mut self) {
+        let charset = match self.default_charset.take() {
+            Some(charset) => charset,
+            None => return,
+        };
+        let value = match self
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(v) if is_text_without_charset(v) => format!("{}; charset={}", v, charset),
+            _ => return,
+        };
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            self.headers_mut().insert(CONTENT_TYPE, value);
         }
     }
 
@@ -50,23 +249,21 @@ impl Response {
     fn write_head_impl(&mut self) -> io::Result<()> {
         let mut writer = self.writer.borrow_mut();
 
+        let status = self.status();
+        let reason = match self.reason {
+            Some(ref reason) => reason.as_str(),
+            None => status.canonical_reason().unwrap_or(""),
+        };
+        write!(
+            writer,
+            "{:?} {} {}\r\nDate: {}\r\n",
+            self.version(),
+            status.as_u16(),
+            reason,
+            ::date::now()
+        )?;
         if let Some(len) = self.body_size {
-            write!(
-                writer,
-                "{:?} {}\r\nDate: {}\r\nContent-Length: {}\r\n",
-                self.version(),
-                self.status(),
-                ::date::now(),
-                len
-            )?;
-        } else {
-            write!(
-                writer,
-                "{:?} {}\r\nDate: {}\r\n",
-                self.version(),
-                self.status(),
-                ::date::now()
-            )?;
+            write!(writer, "Content-Length: {}\r\n", len)?;
         }
 
         for (key, value) in self.headers().iter() {
@@ -76,27 +273,44 @@ impl Response {
             writer.write_all(value.as_bytes())?;
             writer.write_all(b"\r\n")?;
         }
+        writer.write_all(&self.raw_headers)?;
         writer.write_all(b"\r\n")
     }
 
     // write head to stream
     fn write_head(&mut self) -> io::Result<BodyWriter> {
-        let body = match self.status() {
-            StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED => {
-                BodyWriter::EmptyWriter(self.writer.clone())
-            }
-            c if c.is_informational() => BodyWriter::EmptyWriter(self.writer.clone()),
-            _ => {
-                if let Some(size) = self.body_size {
-                    BodyWriter::SizedWriter(self.writer.clone(), size)
-                } else {
-                    self.headers_mut()
-                        .append(TRANSFER_ENCODING, "chunked".parse().unwrap());
-                    BodyWriter::ChunkWriter(self.writer.clone())
-                }
+        let status = self.status();
+        self.apply_default_content_type();
+        if self.http10_client {
+            // e.g. copied from a chunked upstream response by a proxy
+            self.headers_mut().remove(TRANSFER_ENCODING);
+        }
+        let body = if !status_has_body(status) {
+            // the body is dropped, and no framing headers should be sent
+            self.body_size = None;
+            self.headers_mut().remove(TRANSFER_ENCODING);
+            // a 304 may keep the content-length of the selected representation
+            if status != StatusCode::NOT_MODIFIED {
+                self.headers_mut().remove(CONTENT_LENGTH);
             }
+            BodyWriter::EmptyWriter(self.writer.clone())
+        } else if self.head_only || self.detached {
+            BodyWriter::EmptyWriter(self.writer.clone())
+        } else if let Some(size) = self.body_size {
+            BodyWriter::SizedWriter(self.writer.clone(), size)
+        } else if self.http10_client {
+            // the client can't decode the chunked body, close the connection to end it
+            self.headers_mut()
+                .insert(CONNECTION, "close".parse().unwrap());
+            BodyWriter::CloseWriter(self.writer.clone())
+        } else {
+            self.headers_mut()
+                .append(TRANSFER_ENCODING, "chunked".parse().unwrap());
+            BodyWriter::ChunkWriter(self.writer.clone())
         };
         // TODO: sanity check the headers, overwrite content-length header
+        self.apply_default_charset();
+        self.apply_cookie_policy();
 
         self.write_head_impl()?;
         Ok(body)
@@ -135,6 +349,79 @@ impl Response {
         self.write_all(body)
     }
 
+    /// send a RFC 7807 `application/problem+json` error body
+    ///
+    /// the status is set and included in the body along with the `type`,
+    /// `title` and `detail` members. an empty `type_uri` is sent as
+    /// `about:blank`, an empty `detail` is left out
+    pub fn problem(
+        &mut self,
+        status: StatusCode,
+        type_uri: &str,
+        title: &str,
+        detail: &str,
+    ) -> io::Result<()> {
+        let type_uri = if type_uri.is_empty() {
+            "about:blank"
+        } else {
+            type_uri
+        };
+        let mut body = String::from("{\"type\":");
+        push_json_str(&mut body, type_uri);
+        body.push_str(",\"title\":");
+        push_json_str(&mut body, title);
+        body.push_str(&format!(",\"status\":{}", status.as_u16()));
+        if !detail.is_empty() {
+            body.push_str(",\"detail\":");
+            push_json_str(&mut body, detail);
+        }
+        body.push('}');
+
+        *self.status_mut() = status;
+        self.headers_mut()
+            .insert(CONTENT_TYPE, "application/problem+json".parse().unwrap());
+        self.send(body.as_bytes())
+    }
+
+    /// finish the response with an empty body
+    ///
+    /// the head is written right now with `Content-Length: 0`, any
+    /// buffered body, body reader or `Transfer-Encoding` header is dropped.
+    /// a later body write fails. it's an error if the head is already written
+    pub fn set_body_empty(&mut self) -> io::Result<()> {
+        match *self.body() {
+            BodyWriter::InvalidWriter => {}
+            _ => {
+                let msg = "the response head is already written";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        }
+        self.buffer = None;
+        self.body_reader = None;
+        self.headers_mut().remove(TRANSFER_ENCODING);
+        self.body_size = Some(0);
+        *self.body_mut() = self.write_head()?;
+        Ok(())
+    }
+
+    /// write the text to the body with the html special characters escaped
+    ///
+    /// `<`, `>`, `&`, `"` and `'` are written as the character references,
+    /// so the user data can't inject markup. the content type is set to
+    /// `text/html; charset=utf-8` if it's not set and the head is not written
+    pub fn write_html_escaped(&mut self, s: &str) -> io::Result<()> {
+        if let BodyWriter::InvalidWriter = *self.body() {
+            if !self.headers().contains_key(CONTENT_TYPE) {
+                self.headers_mut()
+                    .insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+            }
+        }
+        // escape into one buffer, each write would be a chunk of its own
+        let mut escaped = String::with_capacity(s.len());
+        push_html_escaped(&mut escaped, s);
+        self.write_all(escaped.as_bytes())
+    }
+
     /// set the content-length
     ///
     /// if you don't call `send()`, should call this before write the response
@@ -142,6 +429,616 @@ impl Response {
     pub fn set_content_length(&mut self, len: usize) {
         self.body_size = Some(len);
     }
+
+    /// set the status from the result of a fallible handler
+    ///
+    /// the status is left unchanged for `Ok`, otherwise it's mapped from
+    /// the error kind: `NotFound` to `404`, `PermissionDenied` to `403`,
+    /// `InvalidData`/`InvalidInput` to `400` and others to `500`
+    pub fn set_status_from_result<T>(&mut self, result: &io::Result<T>) {
+        if let Err(ref e) = *result {
+            *self.status_mut() = match e.kind() {
+                io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+        }
+    }
+
+    /// append a pre-serialized header block, e.g. forwarded from upstream
+    ///
+    /// the block is a sequence of `Name: value\r\n` lines that are written
+    /// as is after the headers. it's rejected if any line is malformed, has
+    /// a bare CR or LF, or is empty, and the `Content-Length` and
+    /// `Transfer-Encoding` headers are not allowed since the framing is
+    /// decided by the response. this has no effect once the head is written
+    pub fn append_raw_headers(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let BodyWriter::InvalidWriter = *self.body() {
+            validate_raw_headers(bytes)?;
+            self.raw_headers.extend_from_slice(bytes);
+            Ok(())
+        } else {
+            let msg = "the response head is already written";
+            Err(io::Error::new(io::ErrorKind::Other, msg))
+        }
+    }
+
+    /// write the head and take the underlying connection
+    ///
+    /// this is for the handlers that want full control after the head,
+    /// e.g. long polling or a custom framing. the head is written without
+    /// any body framing and `Connection: close` is added except for `101`,
+    /// the server closes the connection once the handler returns.
+    /// after this the body written to the response is discarded
+    pub fn take_raw_stream(&mut self) -> io::Result<RawStream> {
+        let io = match self.raw_io {
+            Some(ref io) => io.clone(),
+            None => {
+                let msg = "the connection is not available";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        };
+        match *self.body() {
+            BodyWriter::InvalidWriter => {}
+            _ => {
+                let msg = "the response head is already written";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        }
+
+        self.detached = true;
+        self.buffer = None;
+        self.body_reader = None;
+        self.body_size = None;
+        if self.status() != StatusCode::SWITCHING_PROTOCOLS {
+            self.headers_mut()
+                .insert(CONNECTION, "close".parse().unwrap());
+        }
+        *self.body_mut() = self.write_head()?;
+        io.borrow_mut().flush_all()?;
+        Ok(RawStream { io })
+    }
+
+    /// reject the request with the final status without reading its body
+    ///
+    /// `Connection: close` is sent and the connection is closed after the
+    /// response, the unread request body is never drained. a client that
+    /// waits for `100 Continue` is answered before it sends the body
+    pub fn reject(&mut self, status: StatusCode) {
+        *self.status_mut() = status;
+        self.headers_mut()
+            .insert(CONNECTION, "close".parse().unwrap());
+    }
+
+    /// respond `405 Method Not Allowed` with the `Allow` header
+    ///
+    /// RFC 7231 requires a 405 response to list the allowed methods
+    pub fn set_method_not_allowed(&mut self, allowed: &[Method]) {
+        let allow = allowed
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        *self.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        self.headers_mut().insert(ALLOW, allow.parse().unwrap());
+    }
+
+    /// respond to a `TRACE` request by echoing its head as `message/http`
+    ///
+    /// this is used when the server is the final recipient, e.g. the
+    /// `Max-Forwards` is zero. the credential headers like `Cookie` and
+    /// `Authorization` are left out of the echo
+    pub fn send_trace(&mut self, req: &Request) -> io::Result<()> {
+        const SENSITIVE: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+        let mut body = req.request_line().to_vec();
+        body.extend_from_slice(b"\r\n");
+        for (name, value) in req.raw_headers() {
+            let sensitive = SENSITIVE
+                .iter()
+                .any(|s| name.eq_ignore_ascii_case(s.as_bytes()));
+            if sensitive {
+                continue;
+            }
+            body.extend_from_slice(name);
+            body.extend_from_slice(b": ");
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"\r\n");
+        self.headers_mut()
+            .insert(CONTENT_TYPE, "message/http".parse().unwrap());
+        self.send(&body)
+    }
+
+    /// set `Content-Disposition` so the client saves the body as a file
+    ///
+    /// a non-ascii name is sent in the RFC 5987 `filename*` form, with a
+    /// plain `filename` fallback for the old clients
+    pub fn set_attachment(&mut self, filename: &str) {
+        // quoted string of the printable ascii chars
+        let mut fallback = String::with_capacity(filename.len());
+        for c in filename.chars() {
+            match c {
+                '"' | '\\' => {
+                    fallback.push('\\');
+                    fallback.push(c);
+                }
+                ' '..='~' => fallback.push(c),
+                _ => fallback.push('_'),
+            }
+        }
+        let mut value = format!("attachment; filename=\"{}\"", fallback);
+        if !filename.is_ascii() {
+            let encoded = ::percent::percent_encode(filename.as_bytes(), is_attr_char);
+            value.push_str("; filename*=UTF-8''");
+            value.push_str(&encoded);
+        }
+        self.headers_mut()
+            .insert(CONTENT_DISPOSITION, value.parse().unwrap());
+    }
+
+    /// add a backend metric to the `Server-Timing` header
+    ///
+    /// e.g. `add_server_timing("db", dur)` gives `db;dur=12.5` that shows
+    /// in the browser devtools, the metrics are joined into one header.
+    /// the name must be a token
+    pub fn add_server_timing(&mut self, name: &str, dur: Duration) -> io::Result<()> {
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            let msg = "the server timing name is not a token";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let mut value = match self.headers().get("server-timing") {
+            Some(v) => format!("{}, ", v.to_str().unwrap_or("")),
+            None => String::new(),
+        };
+        value.push_str(&format!("{};dur={}", name, fmt_millis(dur)));
+        self.headers_mut()
+            .insert("server-timing", value.parse().unwrap());
+        Ok(())
+    }
+
+    /// add a `WWW-Authenticate` challenge, e.g. `Negotiate` or
+    /// `Negotiate <token>`
+    ///
+    /// it can be called again to offer more schemes. the status is not
+    /// changed, a challenge is usually sent with `401` but the final token
+    /// of a `Negotiate` handshake comes with the successful response
+    pub fn set_www_authenticate(&mut self, challenge: &str) -> io::Result<()> {
+        let value = challenge
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid challenge"))?;
+        self.headers_mut().append(WWW_AUTHENTICATE, value);
+        Ok(())
+    }
+
+    /// set the `Timing-Allow-Origin` header
+    ///
+    /// the browser only exposes the detailed timings to the scripts of the
+    /// listed origins, `*` allows all
+    pub fn set_timing_allow_origin(&mut self, origin: &str) -> io::Result<()> {
+        let value = origin
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid origin"))?;
+        self.headers_mut().insert("timing-allow-origin", value);
+        Ok(())
+    }
+
+    /// ask the client to send the client hints with the later requests
+    ///
+    /// this sets the `Accept-CH` header, e.g. with `["DPR", "Viewport-Width"]`.
+    /// an invalid header name is an `InvalidInput` error. the response that
+    /// depends on a hint should also list it in `Vary`
+    pub fn accept_client_hints(&mut self, hints: &[&str]) -> io::Result<()> {
+        for hint in hints {
+            if HeaderName::from_bytes(hint.as_bytes()).is_err() {
+                let msg = format!("invalid client hint {:?}", hint);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+        let value = hints.join(", ").parse().unwrap();
+        self.headers_mut().insert("accept-ch", value);
+        Ok(())
+    }
+
+    /// override the reason phrase of the status line
+    ///
+    /// by default the canonical reason of the status code is used, the
+    /// reason must not contain control characters other than tab
+    pub fn set_reason(&mut self, reason: &str) -> io::Result<()> {
+        if reason
+            .bytes()
+            .any(|b| (b < b' ' && b != b'\t') || b == 0x7f)
+        {
+            let msg = "the reason phrase contains control characters";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        self.reason = Some(reason.to_owned());
+        Ok(())
+    }
+
+    /// send an informational `1xx` response before the final response
+    ///
+    /// e.g. `103 Early Hints` with `Link` headers for preloading, it can be
+    /// called multiple times but only before the final head is written.
+    /// `101 Switching Protocols` is not allowed here.
+    /// the informational head is flushed to the client right away in any
+    /// `FlushMode`. a HTTP/1.0 client doesn't know the `1xx` responses,
+    /// nothing is sent to it
+    pub fn send_informational(
+        &mut self,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> io::Result<()> {
+        if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+            let msg = format!("{} is not an informational status", status.as_u16());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        match *self.body() {
+            BodyWriter::InvalidWriter => {}
+            _ => {
+                let msg = "the response head is already written";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        }
+        if self.http10_client {
+            return Ok(());
+        }
+
+        let reason = match status.canonical_reason() {
+            Some(reason) => reason,
+            None if status.as_u16() == 103 => "Early Hints",
+            None => "",
+        };
+        {
+            let mut writer = self.writer.borrow_mut();
+            write!(
+                writer,
+                "{:?} {} {}\r\n",
+                self.version(),
+                status.as_u16(),
+                reason
+            )?;
+            for (key, value) in headers.iter() {
+                writer.write_all(key.as_str().as_bytes())?;
+                writer.write_all(b": ")?;
+                writer.write_all(value.as_bytes())?;
+                writer.write_all(b"\r\n")?;
+            }
+            writer.write_all(b"\r\n")?;
+        }
+        // the deferred flush would hold the hints until the final response
+        self.flush_now()
+    }
+
+    // send the data written so far to the client right now,
+    // regardless of the flush mode
+    pub(crate) fn flush_now(&mut self) -> io::Result<()> {
+        if self.needs_downgrade() {
+            // the body is streamed, the length can't be known in advance
+            self.spill_buffer()?;
+        }
+        match self.raw_io {
+            Some(ref io) => io.borrow_mut().flush_all(),
+            None => self.writer.borrow_mut().flush(),
+        }
+    }
+
+    /// reserve capacity for at least `additional` more body bytes
+    ///
+    /// after this the body is buffered in memory and sent out in one go
+    /// when the response is dropped, the content-length is set to the
+    /// buffered size if it's not set, so the chunked encoding is avoided.
+    /// this has no effect once the head is written
+    pub fn reserve(&mut self, additional: usize) {
+        if let BodyWriter::InvalidWriter = *self.body() {
+            self.buffer.get_or_insert_with(Vec::new).reserve(additional);
+        }
+    }
+
+    /// stream the body from the reader when the response is finished
+    ///
+    /// if `len` is `Some` the body is sent with the content-length,
+    /// otherwise the chunked encoding is used. the reader is copied out
+    /// after any data that is already written to the response.
+    /// calling it again replaces the previous reader
+    ///
+    /// with the `gzip` feature a body of unknown size is compressed on the
+    /// fly if the client accepts gzip, nothing is written yet and no
+    /// `Content-Encoding` is set by the handler
+    pub fn set_body_reader(&mut self, src: Box<Read>, len: Option<u64>) {
+        self.body_reader = Some((src, len));
+    }
+
+    // stream out the body reader if any
+    fn copy_body_reader(&mut self) -> io::Result<()> {
+        if let Some((mut src, len)) = self.body_reader.take() {
+            if let Some(len) = len {
+                self.body_size = Some(len as usize);
+            }
+            if self.head_only {
+                return Ok(());
+            }
+            #[cfg(feature = "gzip")]
+            {
+                if self.should_gzip() {
+                    return self.copy_gzip(&mut src);
+                }
+            }
+            io::copy(&mut src, self)?;
+        }
+        Ok(())
+    }
+
+    // check if the streamed body should be compressed
+    #[cfg(feature = "gzip")]
+    fn should_gzip(&self) -> bool {
+        let untouched = match *self.body() {
+            BodyWriter::InvalidWriter => self.buffer.as_ref().is_none_or(|b| b.is_empty()),
+            _ => false,
+        };
+        self.accepts_gzip
+            && untouched
+            && status_has_body(self.status())
+            && self.body_size.is_none()
+            && !self.http10_client
+            && !self.detached
+            && !self.headers().contains_key(CONTENT_ENCODING)
+    }
+
+    // compress the body into the chunked writer, the gzip footer must be
+    // written before the last chunk that is sent when the writer is dropped
+    #[cfg(feature = "gzip")]
+    fn copy_gzip(&mut self, src: &mut Read) -> io::Result<()> {
+        self.buffer = None;
+        {
+            let headers = self.headers_mut();
+            headers.remove(CONTENT_LENGTH);
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        let mut encoder = GzEncoder::new(&mut *self, Compression::default());
+        io::copy(src, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    // check if nothing is written to the body yet
+    pub(crate) fn is_body_untouched(&self) -> bool {
+        match *self.body() {
+            BodyWriter::InvalidWriter => {
+                self.buffer.as_ref().is_none_or(|b| b.is_empty()) && self.body_reader.is_none()
+            }
+            _ => false,
+        }
+    }
+
+    // send the error page rendered by the error handler
+    pub(crate) fn send_error_page(&mut self, page: (HeaderMap, Vec<u8>)) {
+        let (headers, body) = page;
+        self.buffer = None;
+        for key in headers.keys() {
+            self.headers_mut().remove(key);
+        }
+        for (key, value) in headers.iter() {
+            self.headers_mut().append(key.clone(), value.clone());
+        }
+        if let Err(e) = self.send(&body) {
+            error!("failed to write the error page, err={}", e);
+        }
+    }
+
+    // respond the internal error when the handler panicked
+    // the connection should be closed after this
+    pub(crate) fn write_panic_error(&mut self, page: Option<(HeaderMap, Vec<u8>)>) {
+        self.buffer = None;
+        self.body_reader = None;
+        *self.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        self.headers_mut()
+            .insert(CONNECTION, "close".parse().unwrap());
+        match page {
+            Some(page) => self.send_error_page(page),
+            None => {
+                self.write_all(
+                    b"sorry, the server paniced inside!\n\
+                      please contact the service provider!",
+                )
+                .ok();
+            }
+        }
+    }
+
+    // write out the head and the pending body, done when dropped
+    // it's called early to know the final `Connection` header
+    pub(crate) fn finish(&mut self) {
+        if let Err(e) = self.copy_body_reader() {
+            error!("failed to stream the body reader, err={}", e);
+        }
+
+        if let Err(e) = self.flush_buffer() {
+            error!("failed to write the buffered body, err={}", e);
+        }
+
+        // make sure we write every thing
+        if let BodyWriter::InvalidWriter = *self.body() {
+            // no body is written, frame it with the length instead of chunked
+            if self.body_size.is_none()
+                && !self.head_only
+                && (self.http10_client || !self.headers().contains_key(TRANSFER_ENCODING))
+            {
+                self.body_size = Some(0);
+            }
+            *self.body_mut() = self
+                .write_head()
+                .unwrap_or(BodyWriter::EmptyWriter(self.writer.clone()));
+        }
+    }
+
+    // write out the buffered body if any
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        if let Some(buf) = self.buffer.take() {
+            if self.body_size.is_none() {
+                self.body_size = Some(buf.len());
+            }
+            self.write_all(&buf)?;
+        }
+        Ok(())
+    }
+}
+
+// append the text with the html special characters escaped
+pub(crate) fn push_html_escaped(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+// check that the block only has well formed `Name: value\r\n` lines
+fn validate_raw_headers(bytes: &[u8]) -> io::Result<()> {
+    fn invalid(msg: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    }
+
+    if !bytes.is_empty() && !bytes.ends_with(b"\r\n") {
+        return invalid("the header block must end with CRLF");
+    }
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let end = match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None => return invalid("the header block must end with CRLF"),
+        };
+        let line = &rest[..end];
+        rest = &rest[end + 2..];
+
+        let colon = match line.iter().position(|&b| b == b':') {
+            Some(i) if i > 0 => i,
+            _ => return invalid("malformed header line"),
+        };
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid header name"))?;
+        if name == CONTENT_LENGTH || name == TRANSFER_ENCODING {
+            return invalid("the framing headers are not allowed");
+        }
+        // no bare CR/LF or other control characters in the value
+        if line[colon + 1..]
+            .iter()
+            .any(|&b| (b < b' ' && b != b'\t') || b == 0x7f)
+        {
+            return invalid("invalid header value");
+        }
+    }
+    Ok(())
+}
+
+// the chars of a token in RFC 7230
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// format the duration in milliseconds with at most 3 decimals
+fn fmt_millis(dur: Duration) -> String {
+    let micros = dur.as_secs() * 1_000_000 + u64::from(dur.subsec_micros());
+    let (ms, frac) = (micros / 1000, micros % 1000);
+    if frac == 0 {
+        return ms.to_string();
+    }
+    let frac = format!("{:03}", frac);
+    format!("{}.{}", ms, frac.trim_end_matches('0'))
+}
+
+// responses with 1xx, 204 and 304 status must not include a body
+fn status_has_body(status: StatusCode) -> bool {
+    !(status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED)
+}
+
+// append the string as a quoted json string
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// check if the content type is `text/*` and doesn't specify the charset
+fn is_text_without_charset(content_type: &str) -> bool {
+    let lower = content_type.to_ascii_lowercase();
+    lower.starts_with("text/") && !lower.contains("charset=")
+}
+
+// check if the `Set-Cookie` value has the `Secure` attribute
+fn is_secure_cookie(cookie: &[u8]) -> bool {
+    cookie
+        .split(|&b| b == b';')
+        .skip(1)
+        .any(|attr| trim_bytes(attr).eq_ignore_ascii_case(b"secure"))
+}
+
+fn trim_bytes(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&b' ', rest)) = bytes.split_first() {
+        bytes = rest;
+    }
+    while let Some((&b' ', rest)) = bytes.split_last() {
+        bytes = rest;
+    }
+    bytes
+}
+
+// the `attr-char` of RFC 5987
+fn is_attr_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b)
+}
+
+/// the raw connection taken by `Response::take_raw_stream`
+///
+/// the reads and writes bypass the http framing, `flush` always writes
+/// out the buffered data
+pub struct RawStream {
+    io: Rc<RefCell<RawIo>>,
+}
+
+impl fmt::Debug for RawStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<HTTP RawStream>")
+    }
+}
+
+impl Read for RawStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.borrow_mut().read(buf)
+    }
+}
+
+impl Write for RawStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.borrow_mut().write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.borrow_mut().flush_all()
+    }
 }
 
 impl Deref for Response {
@@ -165,7 +1062,22 @@ impl DerefMut for Response {
 impl Write for Response {
     #[inline]
     fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut buf) = self.buffer {
+            buf.extend_from_slice(msg);
+        }
+        if let Some(len) = self.buffer.as_ref().map(|b| b.len()) {
+            if len > MAX_DOWNGRADE_BUFFER && self.needs_downgrade() {
+                // too large to buffer for the content-length
+                self.spill_buffer()?;
+            }
+            return Ok(msg.len());
+        }
         if let BodyWriter::InvalidWriter = *self.body() {
+            if self.needs_downgrade() {
+                // buffer the body to compute the content-length
+                self.buffer = Some(Vec::new());
+                return self.write(msg);
+            }
             *self.body_mut() = self.write_head()?;
         }
         self.body_mut().write(msg)
@@ -182,20 +1094,493 @@ impl Drop for Response {
         use std::thread;
 
         if thread::panicking() {
-            *self.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-            self.write_all(
-                b"sorry, the server paniced inside!\n\
-                  please contact the service provider!",
-            )
-            .ok();
+            self.write_panic_error(None);
             return;
         }
+        self.finish();
+    }
+}
 
-        // make sure we write every thing
-        if let BodyWriter::InvalidWriter = *self.body() {
-            *self.body_mut() = self
-                .write_head()
-                .unwrap_or(BodyWriter::EmptyWriter(self.writer.clone()));
+/// the underlying connection of a `Response`
+pub(crate) trait RawIo: Read + Write {
+    // write out all the buffered data
+    fn flush_all(&mut self) -> io::Result<()>;
+}
+
+impl<S: Read + Write> RawIo for BufferIo<S> {
+    fn flush_all(&mut self) -> io::Result<()> {
+        self.flush_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.reserve(64);
+            let cap = rsp.buffer.as_ref().unwrap().capacity();
+            assert!(cap >= 64);
+            for _ in 0..4 {
+                rsp.write_all(b"hello ").unwrap();
+            }
+            // nothing is written out and the buffer is not grown
+            assert!(out.borrow().is_empty());
+            assert_eq!(rsp.buffer.as_ref().unwrap().capacity(), cap);
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 24\r\n"));
+        assert!(out.ends_with("\r\n\r\nhello hello hello hello "));
+    }
+
+    #[test]
+    fn test_send_informational() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let mut hints = HeaderMap::new();
+            hints.insert(LINK, "</style.css>; rel=preload; as=style".parse().unwrap());
+            let early_hints = StatusCode::from_u16(103).unwrap();
+            rsp.send_informational(early_hints, &hints).unwrap();
+            assert!(rsp.send_informational(StatusCode::OK, &hints).is_err());
+            rsp.send(b"ok").unwrap();
+            assert!(rsp.send_informational(early_hints, &hints).is_err());
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with(
+            "HTTP/1.1 103 Early Hints\r\n\
+             link: </style.css>; rel=preload; as=style\r\n\r\n\
+             HTTP/1.1 200 OK\r\n"
+        ));
+        assert!(out.ends_with("\r\n\r\nok"));
+
+        // not sent to a HTTP/1.0 client
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.set_http10_client();
+            let early_hints = StatusCode::from_u16(103).unwrap();
+            rsp.send_informational(early_hints, &HeaderMap::new())
+                .unwrap();
+            rsp.send(b"ok").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!out.contains("103"));
+    }
+
+    #[test]
+    fn test_set_body_reader_sized() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let src = io::Cursor::new(b"hello world".to_vec());
+            rsp.set_body_reader(Box::new(src), Some(11));
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 11\r\n"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    fn test_set_body_reader_chunked() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let src = io::Cursor::new(b"hello world".to_vec());
+            rsp.set_body_reader(Box::new(src), None);
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(!out.contains("Content-Length"));
+        assert!(out.contains("transfer-encoding: chunked\r\n"));
+        assert!(out.ends_with("\r\n\r\nB\r\nhello world\r\n0\r\n\r\n"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_body_reader() {
+        use body::BodyReader;
+        use flate2::read::GzDecoder;
+        use server::mock::serve_bytes;
+        use server::{HttpServer, Request};
+
+        fn body() -> Vec<u8> {
+            (0..20_000)
+                .flat_map(|i| format!("line {}\n", i).into_bytes())
+                .collect()
+        }
+        fn app(_req: Request, rsp: &mut Response) {
+            rsp.set_body_reader(Box::new(io::Cursor::new(body())), None);
+        }
+        let server = HttpServer::new(app);
+        let out = serve_bytes(&server, b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+        let head_len = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8(out[..head_len].to_vec()).unwrap();
+        assert!(head.contains("content-encoding: gzip\r\n"));
+        assert!(head.contains("transfer-encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert!(out.ends_with(b"0\r\n\r\n"));
+
+        let chunked: Rc<RefCell<Read>> =
+            Rc::new(RefCell::new(io::Cursor::new(out[head_len..].to_vec())));
+        let reader = BodyReader::ChunkReader(chunked, None);
+        let mut decoded = Vec::new();
+        GzDecoder::new(reader).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body());
+
+        // not compressed if the client doesn't ask for it
+        let out = serve_bytes(
+            &server,
+            b"GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0\r\n\r\n",
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("content-encoding"));
+        assert!(out.contains("line 19999\n"));
+    }
+
+    #[test]
+    fn test_no_body_status() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            *rsp.status_mut() = StatusCode::NO_CONTENT;
+            rsp.send(b"hello").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            *rsp.status_mut() = StatusCode::NOT_MODIFIED;
+            rsp.write_all(b"hello").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_set_reason() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            assert!(rsp.set_reason("All\r\nGood").is_err());
+            rsp.set_reason("All Good").unwrap();
+            rsp.send(b"ok").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 All Good\r\n"));
+    }
+
+    #[test]
+    fn test_set_status_from_result() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut rsp = Response::new(out);
+        let cases = [
+            (io::ErrorKind::NotFound, StatusCode::NOT_FOUND),
+            (io::ErrorKind::PermissionDenied, StatusCode::FORBIDDEN),
+            (io::ErrorKind::InvalidData, StatusCode::BAD_REQUEST),
+            (io::ErrorKind::InvalidInput, StatusCode::BAD_REQUEST),
+            (io::ErrorKind::BrokenPipe, StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for &(kind, status) in cases.iter() {
+            let ret: io::Result<()> = Err(io::Error::new(kind, "err"));
+            rsp.set_status_from_result(&ret);
+            assert_eq!(rsp.status(), status);
+        }
+
+        *rsp.status_mut() = StatusCode::CREATED;
+        rsp.set_status_from_result(&Ok(()));
+        assert_eq!(rsp.status(), StatusCode::CREATED);
+    }
+
+    #[test]
+    fn test_append_raw_headers() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let block = b"X-Upstream: a\r\nCache-Control: no-cache\r\n";
+            rsp.append_raw_headers(block).unwrap();
+
+            let bad: &[&[u8]] = &[
+                b"X-A: a",
+                b"X-A: a\nX-B: b\r\n",
+                b"X-A: a\rb\r\n",
+                b"\r\n",
+                b"X-A: a\r\n\r\nX-B: b\r\n",
+                b"No Colon\r\n",
+                b"Bad Name: a\r\n",
+                b"Content-Length: 0\r\n",
+                b"transfer-encoding: chunked\r\n",
+            ];
+            for block in bad {
+                assert!(rsp.append_raw_headers(block).is_err());
+            }
+            rsp.send(b"ok").unwrap();
+            assert!(rsp.append_raw_headers(b"X-Late: a\r\n").is_err());
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("\r\nX-Upstream: a\r\nCache-Control: no-cache\r\n\r\nok"));
+        assert!(!out.contains("X-Late"));
+    }
+
+    #[test]
+    fn test_set_method_not_allowed() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.set_method_not_allowed(&[Method::GET, Method::HEAD, Method::POST]);
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(out.contains("allow: GET, HEAD, POST\r\n"));
+    }
+
+    #[test]
+    fn test_set_attachment() {
+        let mut rsp = Response::new(Rc::new(RefCell::new(Vec::new())));
+        rsp.set_attachment("report 2018.pdf");
+        assert_eq!(
+            rsp.headers()[CONTENT_DISPOSITION],
+            "attachment; filename=\"report 2018.pdf\""
+        );
+        rsp.set_attachment("a\"b\\c.txt");
+        assert_eq!(
+            rsp.headers()[CONTENT_DISPOSITION],
+            "attachment; filename=\"a\\\"b\\\\c.txt\""
+        );
+        rsp.set_attachment("résumé €.txt");
+        assert_eq!(
+            rsp.headers()[CONTENT_DISPOSITION],
+            "attachment; filename=\"r_sum_ _.txt\"; \
+             filename*=UTF-8''r%C3%A9sum%C3%A9%20%E2%82%AC.txt"
+        );
+    }
+
+    #[test]
+    fn test_server_timing() {
+        let mut rsp = Response::new(Rc::new(RefCell::new(Vec::new())));
+        rsp.add_server_timing("db", Duration::from_micros(12_500))
+            .unwrap();
+        rsp.add_server_timing("cache", Duration::from_millis(2))
+            .unwrap();
+        rsp.add_server_timing("app", Duration::from_micros(1_001))
+            .unwrap();
+        assert!(rsp
+            .add_server_timing("a b", Duration::from_millis(1))
+            .is_err());
+        rsp.set_timing_allow_origin("*").unwrap();
+        assert_eq!(
+            rsp.headers()["server-timing"],
+            "db;dur=12.5, cache;dur=2, app;dur=1.001"
+        );
+        assert_eq!(rsp.headers()["timing-allow-origin"], "*");
+    }
+
+    #[test]
+    fn test_http10_downgrade() {
+        // a small body is buffered to compute the content-length
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.set_http10_client();
+            rsp.headers_mut()
+                .insert(TRANSFER_ENCODING, "chunked".parse().unwrap());
+            rsp.write_all(b"hello ").unwrap();
+            rsp.write_all(b"world").unwrap();
+            rsp.finish();
+            assert!(!rsp.headers().contains_key(CONNECTION));
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("Content-Length: 11\r\n"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\nhello world"));
+
+        // a large body is delimited by closing the connection
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let body = vec![b'x'; MAX_DOWNGRADE_BUFFER + 1];
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.set_http10_client();
+            rsp.write_all(&body[..100]).unwrap();
+            rsp.write_all(&body[100..]).unwrap();
+            rsp.finish();
+            assert_eq!(rsp.headers()[CONNECTION], "close");
+        }
+        let out = out.borrow();
+        let head_end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&out[..head_end]);
+        assert!(!head.contains("Content-Length"));
+        assert!(!head.contains("transfer-encoding"));
+        assert!(head.contains("connection: close\r\n"));
+        assert_eq!(&out[head_end..], &body[..]);
+    }
+
+    #[test]
+    fn test_accept_client_hints() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            assert!(rsp.accept_client_hints(&["DPR", "bad hint"]).is_err());
+            rsp.accept_client_hints(&["Save-Data", "DPR", "Viewport-Width"])
+                .unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("accept-ch: Save-Data, DPR, Viewport-Width\r\n"));
+        assert_eq!(out.matches("accept-ch").count(), 1);
+    }
+
+    #[test]
+    fn test_problem() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.problem(
+                StatusCode::FORBIDDEN,
+                "https://example.com/probs/out-of-credit",
+                "You do not have enough credit.",
+                "Your balance is 30, but that costs \"50\".\n",
+            )
+            .unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(out.contains("content-type: application/problem+json\r\n"));
+        assert!(out.ends_with(
+            "\r\n\r\n{\"type\":\"https://example.com/probs/out-of-credit\",\
+             \"title\":\"You do not have enough credit.\",\"status\":403,\
+             \"detail\":\"Your balance is 30, but that costs \\\"50\\\".\\n\"}"
+        ));
+
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.problem(StatusCode::NOT_FOUND, "", "Not Found", "")
+                .unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.ends_with("{\"type\":\"about:blank\",\"title\":\"Not Found\",\"status\":404}"));
+    }
+
+    #[test]
+    fn test_write_html_escaped() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.reserve(64);
+            rsp.write_all(b"<p>").unwrap();
+            rsp.write_html_escaped("<script>alert('x' && \"y\")</script>")
+                .unwrap();
+            rsp.write_all(b"</p>").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("content-type: text/html; charset=utf-8\r\n"));
+        assert!(out.ends_with(
+            "\r\n\r\n<p>&lt;script&gt;alert(&#39;x&#39; &amp;&amp; \
+             &quot;y&quot;)&lt;/script&gt;</p>"
+        ));
+
+        // the escaped text is sent in one chunk
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.write_html_escaped("a<b").unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.ends_with("\r\n\r\n6\r\na&lt;b\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_set_body_empty() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.headers_mut()
+                .insert(TRANSFER_ENCODING, "chunked".parse().unwrap());
+            rsp.reserve(16);
+            rsp.write_all(b"dropped").unwrap();
+            rsp.set_body_empty().unwrap();
+            assert!(rsp.set_body_empty().is_err());
+            assert!(rsp.write_all(b"late").is_err());
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 0\r\n"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_precompressed_body() {
+        // a gzip member header followed by some deflate data
+        let body = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xcbH\xcd\xc9\xc9\x07\x00";
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            rsp.headers_mut()
+                .insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+            rsp.send(body).unwrap();
+        }
+        let out = out.borrow();
+        let head_len = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8(out[..head_len].to_vec()).unwrap();
+        assert_eq!(head.matches("content-encoding").count(), 1);
+        assert!(head.contains("content-encoding: gzip\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert_eq!(&out[head_len..], &body[..]);
+
+        // the streamed body is not compressed again for a gzip client
+        #[cfg(feature = "gzip")]
+        {
+            use body::BodyReader;
+
+            let out = Rc::new(RefCell::new(Vec::new()));
+            {
+                let mut rsp = Response::new(out.clone());
+                rsp.set_accepts_gzip();
+                rsp.headers_mut()
+                    .insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+                rsp.set_body_reader(Box::new(io::Cursor::new(body.to_vec())), None);
+            }
+            let out = out.borrow();
+            let head_len = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            let head = String::from_utf8(out[..head_len].to_vec()).unwrap();
+            assert_eq!(head.matches("content-encoding").count(), 1);
+            let chunked: Rc<RefCell<Read>> =
+                Rc::new(RefCell::new(io::Cursor::new(out[head_len..].to_vec())));
+            let mut sent = Vec::new();
+            BodyReader::ChunkReader(chunked, None)
+                .read_to_end(&mut sent)
+                .unwrap();
+            assert_eq!(sent, &body[..]);
+        }
+    }
+
+    #[test]
+    fn test_empty_body_length() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            *rsp.status_mut() = StatusCode::CREATED;
+            rsp.headers_mut()
+                .insert(LOCATION, "/items/1".parse().unwrap());
         }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(out.contains("Content-Length: 0\r\n"));
+        assert!(!out.contains("transfer-encoding"));
+        assert!(out.ends_with("\r\n\r\n"));
     }
 }
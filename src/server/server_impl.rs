@@ -1,16 +1,33 @@
 //! http server implementation on top of `MAY`
 //!
-use std::cell::RefCell;
-use std::io;
-use std::net::ToSocketAddrs;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
+use body::{self, DEFAULT_MAX_LINE_LENGTH};
 use buffer::BufferIo;
+use http::header::{HeaderMap, CONTENT_LENGTH, RETRY_AFTER, TRANSFER_ENCODING};
+use http::{StatusCode, Version};
 use may::coroutine;
 use may::net::TcpListener;
-use server::HttpService;
+use server::idle::{IdleGuard, IdleTracker};
+use server::ip_limit::IpLimiter;
+use server::proxy_protocol;
+use server::rate_limit::RateLimiter;
+use server::shutdown::{ConnGuard, Shutdown};
+#[cfg(unix)]
+use server::signal::{self, Signal};
+use server::sockopt::{CorkStream, SetReadTimeout, SocketOptions};
+use server::{ErrorHandler, HttpService, Request};
+
+// the max size of the unread request body drained to reuse the connection
+const MAX_DRAIN_SIZE: usize = 64 * 1024;
 
 macro_rules! t {
     ($e: expr) => {
@@ -51,16 +68,103 @@ pub struct HttpServer<T: HttpService> {
     name: String,
     read_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
+    body_timeout: Option<Duration>,
+    max_body_size: Option<usize>,
+    max_line: usize,
+    strict: bool,
+    flush_mode: FlushMode,
+    trust_proxy: bool,
+    max_pipelined: Option<usize>,
+    max_streams: Option<usize>,
+    sock_opts: SocketOptions,
+    nodelay_per_response: bool,
+    accept_backoff: Duration,
+    default_charset: Option<String>,
+    default_content_type: Option<String>,
+    error_handler: Option<Box<ErrorHandler>>,
+    rate_limiter: Option<RateLimiter>,
+    ip_limiter: Option<Arc<IpLimiter>>,
+    idle_tracker: Option<Arc<IdleTracker>>,
+    graceful_timeout: Option<Duration>,
+    write_watermark: usize,
+    insecure_cookies: SecureCookiePolicy,
+    vectored_io: bool,
+    proxy_protocol: bool,
+    default_version: Version,
+    expect_continue: bool,
+    #[cfg(unix)]
+    shutdown_signals: Vec<Signal>,
+    shutdown: Arc<Shutdown>,
+}
+
+/// when to flush the responses to the socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// flush after every response, this gives the lowest latency
+    Immediate,
+    /// flush only when there are no more pipelined requests buffered,
+    /// responses of pipelined requests are coalesced for higher throughput
+    OnIdle,
+}
+
+/// how to handle the `Secure` cookies set on a plaintext connection
+///
+/// a browser silently drops a `Secure` cookie that is set over plain
+/// http, this makes it visible during the development without https
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureCookiePolicy {
+    /// send the cookies as they are
+    Keep,
+    /// remove the `Secure` flag from the cookies
+    Strip,
+    /// don't send the `Secure` cookies
+    Reject,
 }
 
 impl<T: HttpService + Send + Sync + 'static> HttpServer<T> {
     /// create a http server with default configuration
+    ///
+    /// this is a shortcut for `HttpServer::builder(server).build()`
     pub fn new(server: T) -> Self {
-        HttpServer {
-            inner: server,
-            name: String::from("Example"),
-            read_timeout: None,
-            write_timeout: None,
+        HttpServer::builder(server).build()
+    }
+
+    /// create a builder to configure the http server
+    pub fn builder(server: T) -> HttpServerBuilder<T> {
+        HttpServerBuilder {
+            server: HttpServer {
+                inner: server,
+                name: String::from("Example"),
+                read_timeout: None,
+                write_timeout: None,
+                body_timeout: None,
+                max_body_size: None,
+                max_line: DEFAULT_MAX_LINE_LENGTH,
+                strict: false,
+                flush_mode: FlushMode::Immediate,
+                trust_proxy: false,
+                max_pipelined: None,
+                max_streams: None,
+                sock_opts: SocketOptions::default(),
+                nodelay_per_response: false,
+                accept_backoff: Duration::from_millis(100),
+                default_charset: None,
+                default_content_type: None,
+                error_handler: None,
+                rate_limiter: None,
+                ip_limiter: None,
+                idle_tracker: None,
+                graceful_timeout: None,
+                write_watermark: 4096,
+                insecure_cookies: SecureCookiePolicy::Keep,
+                vectored_io: false,
+                proxy_protocol: false,
+                default_version: Version::HTTP_11,
+                expect_continue: true,
+                #[cfg(unix)]
+                shutdown_signals: Vec::new(),
+                shutdown: Arc::new(Shutdown::new()),
+            },
         }
     }
 
@@ -76,63 +180,2078 @@ impl<T: HttpService + Send + Sync + 'static> HttpServer<T> {
         self
     }
 
+    /// set the inactivity timeout of reading the request body
+    ///
+    /// a body read fails if no bytes arrive for the duration, this defends
+    /// against the clients that send the body slowly forever. it replaces
+    /// the read timeout while the handler runs for a request with a body
+    pub fn set_body_inactivity_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.body_timeout = timeout;
+        self
+    }
+
+    /// set the max size of the request body that a handler can read
+    ///
+    /// reading the body beyond the limit returns an `InvalidData` error,
+    /// so a naive `read_to_end` can't buffer an enormous body. the error is
+    /// returned at the first read if the `Content-Length` is over the limit.
+    /// the chunk framing of a chunked body is counted too. the connection is
+    /// closed after a body over the limit instead of reading the rest.
+    /// default is `None` that means no limit
+    pub fn set_max_body_size(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_body_size = max;
+        self
+    }
+
+    /// set the max length of a chunk size line or a trailer line
+    ///
+    /// reading a chunked request body fails with an `InvalidData` error if
+    /// a line is longer, so a line without the CRLF can't be endless.
+    /// default is 4096
+    pub fn set_max_line_length(&mut self, max: usize) -> &mut Self {
+        self.max_line = max;
+        self
+    }
+
+    /// set the `SO_LINGER` option of the accepted connections
+    ///
+    /// with a linger the close would block until the pending data is sent
+    /// or the timeout expires, `None` keeps the OS default
+    pub fn set_linger(&mut self, linger: Option<Duration>) -> &mut Self {
+        self.sock_opts.linger = linger;
+        self
+    }
+
+    /// set how long to sleep after a transient accept error
+    ///
+    /// e.g. the accept fails with `EMFILE` when the fds are exhausted,
+    /// sleeping a while avoids the accept loop spinning hot. the fatal
+    /// errors of the listener stop the server. default is 100ms
+    pub fn set_accept_error_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.accept_backoff = backoff;
+        self
+    }
+
+    /// enable the TCP keepalive of the accepted connections
+    ///
+    /// the probes start after the connection is idle for the duration,
+    /// this detects the dead peers of the long-lived connections.
+    /// `None` keeps the OS default
+    pub fn set_tcp_keepalive(&mut self, keepalive: Option<Duration>) -> &mut Self {
+        self.sock_opts.keepalive = keepalive;
+        self
+    }
+
+    /// set the `SO_RCVBUF` size of the accepted connections
+    ///
+    /// a larger buffer helps the uploads over a link with a high latency.
+    /// the OS may clamp the value or adjust it for its own bookkeeping,
+    /// e.g. linux doubles it. `None` keeps the OS default
+    pub fn set_recv_buffer_size(&mut self, size: Option<usize>) -> &mut Self {
+        self.sock_opts.recv_buffer = size;
+        self
+    }
+
+    /// set the `SO_SNDBUF` size of the accepted connections
+    ///
+    /// a larger buffer helps the downloads over a link with a high latency.
+    /// the OS may clamp the value or adjust it for its own bookkeeping,
+    /// e.g. linux doubles it. `None` keeps the OS default
+    pub fn set_send_buffer_size(&mut self, size: Option<usize>) -> &mut Self {
+        self.sock_opts.send_buffer = size;
+        self
+    }
+
+    /// cork the socket around the response writes
+    ///
+    /// the socket is corked while the responses are assembled and
+    /// uncorked when they are flushed, so the packets are batched but
+    /// still sent out promptly. this uses `TCP_CORK` on linux and toggles
+    /// `TCP_NODELAY` elsewhere, at the cost of two `setsockopt` calls per
+    /// flush. default is `false`
+    pub fn set_nodelay_per_response(&mut self, enable: bool) -> &mut Self {
+        self.nodelay_per_response = enable;
+        self
+    }
+
     /// set the serer name
     pub fn set_server_name(&mut self, name: String) -> &mut Self {
         self.name = name;
         self
     }
 
+    /// enable the strict RFC compliance checks for incoming requests
+    ///
+    /// requests that fail `Request::validate` are rejected with `400`
+    pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// set when to flush the responses, default is `FlushMode::Immediate`
+    pub fn set_flush_mode(&mut self, mode: FlushMode) -> &mut Self {
+        self.flush_mode = mode;
+        self
+    }
+
+    /// set the max number of pipelined requests whose responses can be
+    /// coalesced before a flush, only useful with `FlushMode::OnIdle`
+    ///
+    /// once the limit is reached the responses are flushed and no more
+    /// requests are read until the client consumes them
+    pub fn set_max_pipelined_requests(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_pipelined = max;
+        self
+    }
+
+    /// set the max number of pipelined requests served in a row
+    ///
+    /// after that the connection yields to the scheduler before serving
+    /// the next buffered request, so one connection with a deep pipeline
+    /// doesn't starve the others on the same worker. unlike
+    /// `set_max_pipelined_requests` nothing is flushed. default is `None`,
+    /// a connection only yields when it waits for the socket
+    pub fn set_max_concurrent_streams_per_connection(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_streams = max;
+        self
+    }
+
+    /// trust the `X-Forwarded-Proto` and `X-Forwarded-Host` headers
+    ///
+    /// only enable this if the server is behind a proxy that sets them
+    pub fn set_trust_proxy(&mut self, trust: bool) -> &mut Self {
+        self.trust_proxy = trust;
+        self
+    }
+
+    /// set the charset that is appended to the `text/*` content types
+    ///
+    /// e.g. with `Some("utf-8")` a `text/html` response is sent as
+    /// `text/html; charset=utf-8`, the non text types and the types that
+    /// already specify a charset are left alone. default is `None`
+    pub fn set_default_charset(&mut self, charset: Option<&str>) -> &mut Self {
+        self.default_charset = charset.map(|c| c.to_owned());
+        self
+    }
+
+    /// set the content type of the responses that have a body but no type
+    ///
+    /// e.g. `application/octet-stream` or `text/plain`, the types set by
+    /// the handler are left alone, and so are the responses without a body.
+    /// the default charset is appended to it if it's a text type. default
+    /// is `None`, the response is sent without a content type
+    pub fn set_default_content_type(&mut self, content_type: Option<&str>) -> &mut Self {
+        self.default_content_type = content_type.map(|c| c.to_owned());
+        self
+    }
+
+    /// set the handler that renders the error responses
+    ///
+    /// it's called with the status and the request for the errors that are
+    /// generated by the server, e.g. `400` for the invalid requests in strict
+    /// mode, `500` for a panicked handler, and the `4xx`/`5xx` responses that
+    /// have an empty body like the `404` from `Response::serve_file`.
+    /// the returned headers and body are used for the response, the default
+    /// is an empty body
+    pub fn set_error_handler<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(StatusCode, &Request) -> (HeaderMap, Vec<u8>) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Box::new(f));
+        self
+    }
+
+    /// set the max number of requests per second across the whole server
+    ///
+    /// this is a global ceiling to protect a fragile backend, a burst of
+    /// up to `rate` requests is allowed. the requests over the limit are
+    /// responded with `503` and `Retry-After`, and the connection is closed.
+    /// default is `None` that means no limit
+    pub fn set_max_request_rate(&mut self, rate: Option<u32>) -> &mut Self {
+        self.rate_limiter = rate.map(RateLimiter::new);
+        self
+    }
+
+    /// set the max number of concurrent connections from one client ip
+    ///
+    /// the connections over the limit are closed right after accepted,
+    /// this stops a single client from exhausting the fds. default is
+    /// `None` that means no limit
+    pub fn set_connection_limit_per_ip(&mut self, max: Option<usize>) -> &mut Self {
+        self.ip_limiter = max.map(|max| Arc::new(IpLimiter::new(max)));
+        self
+    }
+
+    /// set the max number of idle keep-alive connections
+    ///
+    /// a connection is idle when it waits for the next request after a
+    /// response, once there are more idle connections than the limit the
+    /// one that has been idle the longest is closed. this bounds the fds
+    /// held by many clients that keep their connections open, along with
+    /// the read timeout. default is `None` that means no limit
+    pub fn set_max_keepalive_idle_connections(&mut self, max: Option<usize>) -> &mut Self {
+        self.idle_tracker = max.map(|max| Arc::new(IdleTracker::new(max)));
+        self
+    }
+
+    /// set how long the shutdown waits for the in-flight requests
+    ///
+    /// the connections that are still alive after the timeout are forcibly
+    /// closed, so a stuck handler can't block the shutdown forever.
+    /// default is `None` that waits until all the connections are done
+    pub fn set_graceful_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.graceful_timeout = timeout;
+        self
+    }
+
+    /// set the max size of the response data buffered for a connection
+    ///
+    /// once the buffer is full it's written out to the socket before more
+    /// is buffered, so a handler that writes faster than the client reads
+    /// is blocked instead of growing the memory. default is 4096
+    pub fn set_response_buffer_high_watermark(&mut self, size: usize) -> &mut Self {
+        self.write_watermark = size;
+        self
+    }
+
+    /// set how to handle the `Secure` cookies when `Request::is_secure` is false
+    ///
+    /// default is `SecureCookiePolicy::Keep`
+    pub fn set_secure_cookie_policy(&mut self, policy: SecureCookiePolicy) -> &mut Self {
+        self.insecure_cookies = policy;
+        self
+    }
+
+    /// use the vectored writes for the responses
+    ///
+    /// a large body write is sent along with the buffered head in one
+    /// `writev` instead of being copied through the write buffer. the
+    /// reads already go straight into the request buffer, so they are not
+    /// changed. default is `false`
+    pub fn set_vectored_io(&mut self, vectored: bool) -> &mut Self {
+        self.vectored_io = vectored;
+        self
+    }
+
+    /// expect the PROXY protocol header at the start of each connection
+    ///
+    /// it's prepended by a TCP load balancer like HAProxy or AWS NLB, both
+    /// the text v1 and the binary v2 forms are accepted. the header is
+    /// stripped and `Request::remote_addr` is the real client address.
+    /// a connection without the header is closed. default is `false`
+    pub fn set_proxy_protocol(&mut self, enable: bool) -> &mut Self {
+        self.proxy_protocol = enable;
+        self
+    }
+
+    /// set the http version of the responses generated by the server
+    ///
+    /// e.g. the `400` for a request that can't be parsed, or the `417` for
+    /// an unsupported expectation, where the request version is unknown or
+    /// not used. only `HTTP/1.0` is meaningful besides the default
+    /// `HTTP/1.1`, any other version is taken as `HTTP/1.1`
+    pub fn set_default_response_version(&mut self, version: Version) -> &mut Self {
+        self.default_version = match version {
+            Version::HTTP_10 => Version::HTTP_10,
+            _ => Version::HTTP_11,
+        };
+        self
+    }
+
+    /// handle the `Expect` header of the requests
+    ///
+    /// when enabled the `100 Continue` is sent at the first body read, and
+    /// an unsupported expectation is rejected with `417`. disable it if a
+    /// reverse proxy already handles the expectation, then the header is
+    /// ignored and the body is just read. default is `true`
+    pub fn set_expect_continue(&mut self, enable: bool) -> &mut Self {
+        self.expect_continue = enable;
+        self
+    }
+
+    /// set the callback that is invoked once the server is shutdown
+    ///
+    /// it's called after the listener is closed and all the connections are drained
+    pub fn on_shutdown<F: FnOnce() + Send + 'static>(&mut self, f: F) -> &mut Self {
+        self.shutdown.set_callback(Box::new(f));
+        self
+    }
+
+    /// gracefully shutdown the server when the signal is received
+    ///
+    /// it can be called for both `SIGTERM` and `SIGINT`. the handler of the
+    /// signal is installed when the server starts, it replaces the default
+    /// action of exiting the process. the process still exits once the
+    /// server is waited in `main`
+    #[cfg(unix)]
+    pub fn set_shutdown_on_signal(&mut self, signal: Signal) -> &mut Self {
+        if !self.shutdown_signals.contains(&signal) {
+            self.shutdown_signals.push(signal);
+        }
+        self
+    }
+
     /// Spawns the http service, binding to the given address
-    /// return a coroutine that you can cancel it when need to stop the service
-    pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
+    /// return a handle that you can use to wait or shutdown the service
+    pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<ServerHandle> {
         let listener = TcpListener::bind(addr)?;
-        go!(
+        self.serve_listener(listener)
+    }
+
+    /// Spawns the http service on an already bound listener
+    ///
+    /// e.g. adopt the listener fd passed from the old process for a hot
+    /// reload, see `ServerHandle::listener_fd`
+    pub fn serve_listener(self, listener: TcpListener) -> io::Result<ServerHandle> {
+        #[cfg(unix)]
+        let listener_fd = {
+            use std::os::unix::io::AsRawFd;
+            listener.as_raw_fd()
+        };
+        let shutdown = self.shutdown.clone();
+        let local_addr = listener.local_addr()?;
+        shutdown.set_addr(local_addr);
+        #[cfg(unix)]
+        signal::watch(&self.shutdown_signals, shutdown.clone())?;
+        let join = go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
             move || {
                 let server = Arc::new(self);
                 for stream in listener.incoming() {
-                    let mut stream = t_c!(stream);
+                    if server.shutdown.is_requested() {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(s) => s,
+                        Err(e) => match accept_retry_delay(&e, server.accept_backoff) {
+                            Some(delay) => {
+                                error!("accept err={}, retry after {:?}", e, delay);
+                                coroutine::sleep(delay);
+                                continue;
+                            }
+                            None => {
+                                error!("accept err={}, stop the server", e);
+                                break;
+                            }
+                        },
+                    };
+                    let ip_guard = match server.ip_limiter {
+                        Some(ref limiter) => {
+                            let ip = t_c!(stream.peer_addr()).ip();
+                            match IpLimiter::acquire(limiter, ip) {
+                                Some(guard) => Some(guard),
+                                None => {
+                                    info!("reject connection from {}: too many connections", ip);
+                                    continue;
+                                }
+                            }
+                        }
+                        None => None,
+                    };
                     t_c!(stream.set_read_timeout(server.read_timeout));
                     t_c!(stream.set_write_timeout(server.write_timeout));
+                    t_c!(server.sock_opts.apply(&stream));
                     let server = server.clone();
+                    let guard = t_c!(ConnGuard::new(
+                        server.shutdown.clone(),
+                        &stream,
+                        server.graceful_timeout.is_some()
+                    ));
+                    let idle_guard = match server.idle_tracker {
+                        Some(ref tracker) => Some(t_c!(IdleTracker::register(tracker, &stream))),
+                        None => None,
+                    };
+                    let peer = stream.peer_addr().ok();
                     go!(move || {
-                        let mut stream = BufferIo::new(stream);
-                        loop {
-                            match t!(super::request::decode(stream.get_reader_buf())) {
-                                None => {
-                                    // need more data
-                                    if t!(stream.bump_read()) == 0 {
-                                        // break the connection
-                                        return;
-                                    };
-                                }
-                                Some(req) => {
-                                    if !t!(super::handle_expect(&req, &mut stream)) {
-                                        // close the connection
-                                        return;
-                                    };
-                                    let io = Rc::new(RefCell::new(stream));
-                                    if !super::process_request(
-                                        &server.inner,
-                                        &server.name,
-                                        req,
-                                        io.clone(),
-                                    ) {
-                                        // close the connection
-                                        return;
-                                    }
-                                    // since handle is done, the reader should be released
-                                    stream = Rc::try_unwrap(io).expect("no reader").into_inner();
-                                }
-                            }
+                        if server.nodelay_per_response {
+                            let stream = CorkStream::new(stream);
+                            server.serve_connection(stream, peer, idle_guard.as_ref());
+                        } else {
+                            server.serve_connection(stream, peer, idle_guard.as_ref());
                         }
+                        drop(idle_guard);
+                        drop(ip_guard);
+                        drop(guard);
                     });
                 }
+                // stop accepting and wait all the connections drained
+                drop(listener);
+                server.shutdown.wait_conns(server.graceful_timeout);
+                server.shutdown.finish();
             }
-        )
+        )?;
+        Ok(ServerHandle {
+            join,
+            shutdown,
+            local_addr,
+            #[cfg(unix)]
+            listener_fd,
+        })
+    }
+
+    // serve the requests on the connection until it's closed
+    pub(crate) fn serve_connection<S>(
+        &self,
+        stream: S,
+        peer: Option<SocketAddr>,
+        idle: Option<&IdleGuard>,
+    ) where
+        S: Read + Write + SetReadTimeout + 'static,
+    {
+        // the chunked request bodies are read in this coroutine
+        body::set_max_line_length(self.max_line);
+        let mut stream = BufferIo::new(stream);
+        stream.set_write_capacity(self.write_watermark);
+        stream.set_vectored(self.vectored_io);
+        stream.set_defer_flush(self.flush_mode == FlushMode::OnIdle);
+        let stream = Rc::new(RefCell::new(stream));
+        self.serve_requests(&stream, peer, idle);
+        // write out all the pending responses before close
+        let ret = stream.borrow_mut().flush_buf();
+        if let Err(e) = ret {
+            error!("flush connection err={}", e);
+        }
+    }
+
+    fn serve_requests<S>(
+        &self,
+        stream: &Rc<RefCell<BufferIo<S>>>,
+        peer: Option<SocketAddr>,
+        idle: Option<&IdleGuard>,
+    ) where
+        S: Read + Write + SetReadTimeout + 'static,
+    {
+        let remote_addr = if self.proxy_protocol {
+            t!(read_proxy_header(&mut *stream.borrow_mut(), peer))
+        } else {
+            peer
+        };
+        // number of the pipelined requests that are not flushed
+        let mut pipelined = 0;
+        // number of the empty lines skipped before the next request
+        let mut skipped = 0;
+        // number of the requests served since the last yield
+        let mut streak = 0;
+        // a response is sent, the connection is kept alive for the next one
+        let mut served = false;
+        loop {
+            // the buffer must not be borrowed when writing the error
+            let decoded =
+                super::request::decode(stream.borrow_mut().get_reader_buf(), &mut skipped);
+            let req = match decoded {
+                Ok(req) => req,
+                Err(e) => {
+                    let mut s = stream.borrow_mut();
+                    if super::request::is_h2_preface(s.get_reader_buf()) {
+                        // it doesn't understand a HTTP/1.x error response
+                        info!("reject the HTTP/2 connection, only HTTP/1.x is supported");
+                        t!(super::write_h2_goaway(&mut *s));
+                        return;
+                    }
+                    info!("reject request: {}", e);
+                    let status = StatusCode::BAD_REQUEST;
+                    t!(super::write_error(
+                        &mut *s,
+                        self.default_version,
+                        status,
+                        None
+                    ));
+                    return;
+                }
+            };
+            match req {
+                None => {
+                    let mut stream = stream.borrow_mut();
+                    // the connection is idle, write out the pending responses
+                    t!(stream.flush_buf());
+                    pipelined = 0;
+                    streak = 0;
+                    // it's idle if no part of the next request is received
+                    let idle = idle.filter(|_| served && stream.get_reader_buf().is_empty());
+                    if let Some(idle) = idle {
+                        idle.set_idle();
+                    }
+                    // need more data
+                    let n = t!(stream.bump_read());
+                    if let Some(idle) = idle {
+                        idle.set_busy();
+                    }
+                    if n == 0 {
+                        // break the connection
+                        return;
+                    };
+                }
+                Some(mut req) => {
+                    req.set_trust_proxy(self.trust_proxy);
+                    // the rest of a too large body is not drained
+                    let exceeded = Rc::new(Cell::new(false));
+                    if let Some(max) = self.max_body_size {
+                        req.set_body_limit(max, exceeded.clone());
+                    }
+                    req.set_remote_addr(remote_addr);
+                    if let Err(e) = req.content_length() {
+                        info!("reject request {:?}: {}", req, e);
+                        let mut s = stream.borrow_mut();
+                        let status = StatusCode::BAD_REQUEST;
+                        let page = self.error_handler.as_ref().map(|h| h(status, &req));
+                        t!(super::write_error(
+                            &mut *s,
+                            self.default_version,
+                            status,
+                            page
+                        ));
+                        return;
+                    }
+                    if self.strict {
+                        if let Err(e) = req.validate() {
+                            info!("reject request {:?}: {}", req, e);
+                            let mut s = stream.borrow_mut();
+                            let status = StatusCode::BAD_REQUEST;
+                            let page = self.error_handler.as_ref().map(|h| h(status, &req));
+                            t!(super::write_error(
+                                &mut *s,
+                                self.default_version,
+                                status,
+                                page
+                            ));
+                            return;
+                        }
+                    }
+                    if !self.rate_limiter.as_ref().is_none_or(|r| r.acquire()) {
+                        info!("reject request {:?}: rate limit exceeded", req);
+                        let status = StatusCode::SERVICE_UNAVAILABLE;
+                        let (mut headers, body) = match self.error_handler {
+                            Some(ref h) => h(status, &req),
+                            None => (HeaderMap::new(), Vec::new()),
+                        };
+                        headers.insert(RETRY_AFTER, "1".parse().unwrap());
+                        let mut s = stream.borrow_mut();
+                        t!(super::write_error(
+                            &mut *s,
+                            self.default_version,
+                            status,
+                            Some((headers, body))
+                        ));
+                        return;
+                    }
+                    let error_handler = self.error_handler.as_deref();
+                    if self.expect_continue
+                        && !t!(super::handle_expect(
+                            &req,
+                            &mut *stream.borrow_mut(),
+                            self.default_version,
+                            error_handler
+                        ))
+                    {
+                        // close the connection
+                        return;
+                    };
+                    // send the `100 Continue` only when the handler reads the body
+                    if self.expect_continue && super::expects_continue(&req) {
+                        let stream = stream.clone();
+                        req.set_continue(Box::new(move || {
+                            super::write_continue(&mut *stream.borrow_mut())
+                        }));
+                    }
+                    // the size of the body left unread by the handler
+                    let body_left = Rc::new(Cell::new(Some(0)));
+                    req.set_body_left(body_left.clone());
+                    let has_body = req.headers().contains_key(CONTENT_LENGTH)
+                        || req.headers().contains_key(TRANSFER_ENCODING);
+                    let body_timeout = self.body_timeout.filter(|_| has_body);
+                    if body_timeout.is_some() {
+                        t!(stream
+                            .borrow_mut()
+                            .inner_mut()
+                            .set_read_timeout(body_timeout));
+                    }
+                    let defaults = super::ContentDefaults {
+                        charset: self.default_charset.as_deref(),
+                        content_type: self.default_content_type.as_deref(),
+                    };
+                    let cookie_policy = if req.is_secure() {
+                        SecureCookiePolicy::Keep
+                    } else {
+                        self.insecure_cookies
+                    };
+                    if !super::process_request(
+                        &self.inner,
+                        &self.name,
+                        defaults,
+                        cookie_policy,
+                        error_handler,
+                        req,
+                        stream.clone(),
+                    ) {
+                        // close the connection
+                        return;
+                    }
+                    if body_timeout.is_some() {
+                        t!(stream
+                            .borrow_mut()
+                            .inner_mut()
+                            .set_read_timeout(self.read_timeout));
+                    }
+                    match body_left.get() {
+                        Some(0) => {}
+                        Some(n) if n <= MAX_DRAIN_SIZE => {
+                            // drain the unread body after the response is sent
+                            let mut s = stream.borrow_mut();
+                            t!(s.flush_buf());
+                            let drained =
+                                t!(io::copy(&mut (&mut *s).take(n as u64), &mut io::sink()));
+                            if drained < n as u64 {
+                                return;
+                            }
+                        }
+                        // too much or unknown body is left, or the client may
+                        // still send the body that is not continued
+                        _ => return,
+                    }
+                    if self.shutdown.is_requested() {
+                        // don't serve more requests when shutting down
+                        return;
+                    }
+                    served = true;
+                    pipelined += 1;
+                    if Some(pipelined) == self.max_pipelined {
+                        // block on writing until the client reads the responses
+                        // and don't read more requests before that
+                        t!(stream.borrow_mut().flush_buf());
+                        pipelined = 0;
+                    }
+                    streak += 1;
+                    if Some(streak) == self.max_streams {
+                        // give the other connections a chance to run
+                        coroutine::yield_now();
+                        streak = 0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// read and strip the PROXY protocol header, return the real client address
+fn read_proxy_header<S: Read + Write>(
+    stream: &mut BufferIo<S>,
+    peer: Option<SocketAddr>,
+) -> io::Result<Option<SocketAddr>> {
+    loop {
+        if let Some((len, addr)) = proxy_protocol::parse(stream.get_reader_buf())? {
+            stream.get_reader_buf().split_to(len);
+            // a health check of the proxy itself has no client address
+            return Ok(addr.or(peer));
+        }
+        if stream.bump_read()? == 0 {
+            let msg = "incomplete PROXY protocol header";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+    }
+}
+
+// return how long to wait before accepting again, `None` if the error is fatal
+fn accept_retry_delay(e: &io::Error, backoff: Duration) -> Option<Duration> {
+    match e.kind() {
+        // the peer is gone before accepted, just retry
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock => Some(Duration::from_millis(0)),
+        // the listener is not usable any more
+        io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected => None,
+        // EBADF
+        _ if cfg!(unix) && e.raw_os_error() == Some(9) => None,
+        // e.g. `EMFILE`/`ENFILE`/`ENOMEM`, wait for the resources released
+        _ => Some(backoff),
+    }
+}
+
+/// the handle of a running `HttpServer`
+pub struct ServerHandle {
+    join: coroutine::JoinHandle<()>,
+    shutdown: Arc<Shutdown>,
+    local_addr: SocketAddr,
+    #[cfg(unix)]
+    listener_fd: RawFd,
+}
+
+impl ServerHandle {
+    /// return the address that the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// return the raw fd of the listener
+    ///
+    /// a supervisor can pass it to a new process that adopts it with
+    /// `HttpServer::serve_listener`. the fd is still owned by the server and
+    /// closed when the server exits, so it should be duplicated before
+    /// shutting down the server
+    #[cfg(unix)]
+    pub fn listener_fd(&self) -> RawFd {
+        self.listener_fd
+    }
+
+    /// block until the server exits
+    pub fn wait(&self) {
+        self.join.wait()
+    }
+
+    /// wait the server exits and return its result
+    pub fn join(self) -> thread::Result<()> {
+        self.join.join()
+    }
+
+    /// return the coroutine that runs the accept loop
+    pub fn coroutine(&self) -> &coroutine::Coroutine {
+        self.join.coroutine()
+    }
+
+    /// gracefully shutdown the server
+    ///
+    /// the server stops accepting new connections, the alive connections
+    /// are closed after the in-flight request is done, and then the
+    /// `on_shutdown` callback is invoked. this function doesn't block,
+    /// call `wait` or `join` to wait for the server exits.
+    /// see `HttpServer::set_graceful_timeout` to bound the wait
+    pub fn shutdown(&self) {
+        self.shutdown.request()
+    }
+}
+
+/// builder for `HttpServer`
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use may_http::server::*;
+/// # fn hello(_req: Request, rsp: &mut Response) {}
+/// let server = HttpServer::builder(hello)
+///     .server_name("may_http")
+///     .read_timeout(Some(Duration::from_secs(10)))
+///     .start("127.0.0.1:8080")
+///     .unwrap();
+/// server.wait();
+/// ```
+pub struct HttpServerBuilder<T: HttpService> {
+    server: HttpServer<T>,
+}
+
+impl<T: HttpService + Send + Sync + 'static> HttpServerBuilder<T> {
+    /// set read timeout
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.server.set_read_timeout(timeout);
+        self
+    }
+
+    /// set write timeout
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.server.set_write_timeout(timeout);
+        self
+    }
+
+    /// set the inactivity timeout of reading the request body
+    pub fn body_inactivity_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.server.set_body_inactivity_timeout(timeout);
+        self
+    }
+
+    /// set the max size of the request body that a handler can read
+    pub fn max_body_size(mut self, max: Option<usize>) -> Self {
+        self.server.set_max_body_size(max);
+        self
+    }
+
+    /// set the max length of a chunk size line or a trailer line
+    pub fn max_line_length(mut self, max: usize) -> Self {
+        self.server.set_max_line_length(max);
+        self
+    }
+
+    /// set the `SO_LINGER` option of the accepted connections
+    pub fn linger(mut self, linger: Option<Duration>) -> Self {
+        self.server.set_linger(linger);
+        self
+    }
+
+    /// set how long to sleep after a transient accept error
+    pub fn accept_error_backoff(mut self, backoff: Duration) -> Self {
+        self.server.set_accept_error_backoff(backoff);
+        self
+    }
+
+    /// enable the TCP keepalive of the accepted connections
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.server.set_tcp_keepalive(keepalive);
+        self
+    }
+
+    /// set the `SO_RCVBUF` size of the accepted connections
+    pub fn recv_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.server.set_recv_buffer_size(size);
+        self
+    }
+
+    /// set the `SO_SNDBUF` size of the accepted connections
+    pub fn send_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.server.set_send_buffer_size(size);
+        self
+    }
+
+    /// cork the socket around the response writes
+    pub fn nodelay_per_response(mut self, enable: bool) -> Self {
+        self.server.set_nodelay_per_response(enable);
+        self
+    }
+
+    /// set the server name
+    pub fn server_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.server.set_server_name(name.into());
+        self
+    }
+
+    /// enable the strict RFC compliance checks
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.server.set_strict(strict);
+        self
+    }
+
+    /// finish the configuration and create the http server
+    pub fn build(self) -> HttpServer<T> {
+        self.server
+    }
+
+    /// set when to flush the responses
+    pub fn flush_mode(mut self, mode: FlushMode) -> Self {
+        self.server.set_flush_mode(mode);
+        self
+    }
+
+    /// set the max number of pipelined requests before a flush
+    pub fn max_pipelined_requests(mut self, max: Option<usize>) -> Self {
+        self.server.set_max_pipelined_requests(max);
+        self
+    }
+
+    /// set the max number of pipelined requests served in a row
+    pub fn max_concurrent_streams_per_connection(mut self, max: Option<usize>) -> Self {
+        self.server.set_max_concurrent_streams_per_connection(max);
+        self
+    }
+
+    /// trust the `X-Forwarded-Proto` and `X-Forwarded-Host` headers
+    pub fn trust_proxy(mut self, trust: bool) -> Self {
+        self.server.set_trust_proxy(trust);
+        self
+    }
+
+    /// set the charset that is appended to the `text/*` content types
+    pub fn default_charset(mut self, charset: Option<&str>) -> Self {
+        self.server.set_default_charset(charset);
+        self
+    }
+
+    /// set the content type of the responses that have a body but no type
+    pub fn default_content_type(mut self, content_type: Option<&str>) -> Self {
+        self.server.set_default_content_type(content_type);
+        self
+    }
+
+    /// set the handler that renders the error responses
+    pub fn error_handler<F>(mut self, f: F) -> Self
+    where
+        F: Fn(StatusCode, &Request) -> (HeaderMap, Vec<u8>) + Send + Sync + 'static,
+    {
+        self.server.set_error_handler(f);
+        self
+    }
+
+    /// set the max number of requests per second across the whole server
+    pub fn max_request_rate(mut self, rate: Option<u32>) -> Self {
+        self.server.set_max_request_rate(rate);
+        self
+    }
+
+    /// set the max number of idle keep-alive connections
+    pub fn max_keepalive_idle_connections(mut self, max: Option<usize>) -> Self {
+        self.server.set_max_keepalive_idle_connections(max);
+        self
+    }
+
+    /// set the max number of concurrent connections from one client ip
+    pub fn connection_limit_per_ip(mut self, max: Option<usize>) -> Self {
+        self.server.set_connection_limit_per_ip(max);
+        self
+    }
+
+    /// set how long the shutdown waits for the in-flight requests
+    pub fn graceful_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.server.set_graceful_timeout(timeout);
+        self
+    }
+
+    /// set the max size of the response data buffered for a connection
+    pub fn response_buffer_high_watermark(mut self, size: usize) -> Self {
+        self.server.set_response_buffer_high_watermark(size);
+        self
+    }
+
+    /// set how to handle the `Secure` cookies on a plaintext connection
+    pub fn secure_cookie_policy(mut self, policy: SecureCookiePolicy) -> Self {
+        self.server.set_secure_cookie_policy(policy);
+        self
+    }
+
+    /// use the vectored writes for the responses
+    pub fn vectored_io(mut self, vectored: bool) -> Self {
+        self.server.set_vectored_io(vectored);
+        self
+    }
+
+    /// expect the PROXY protocol header at the start of each connection
+    pub fn proxy_protocol(mut self, enable: bool) -> Self {
+        self.server.set_proxy_protocol(enable);
+        self
+    }
+
+    /// handle the `Expect` header of the requests
+    pub fn expect_continue(mut self, enable: bool) -> Self {
+        self.server.set_expect_continue(enable);
+        self
+    }
+
+    /// set the http version of the responses generated by the server
+    pub fn default_response_version(mut self, version: Version) -> Self {
+        self.server.set_default_response_version(version);
+        self
+    }
+
+    /// set the callback that is invoked once the server is shutdown
+    pub fn on_shutdown<F: FnOnce() + Send + 'static>(mut self, f: F) -> Self {
+        self.server.on_shutdown(f);
+        self
+    }
+
+    /// gracefully shutdown the server when the signal is received
+    #[cfg(unix)]
+    pub fn shutdown_on_signal(mut self, signal: Signal) -> Self {
+        self.server.set_shutdown_on_signal(signal);
+        self
+    }
+
+    /// build the http server and spawn it, binding to the given address
+    pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<ServerHandle> {
+        self.build().start(addr)
+    }
+
+    /// build the http server and spawn it on an already bound listener
+    pub fn serve_listener(self, listener: TcpListener) -> io::Result<ServerHandle> {
+        self.build().serve_listener(listener)
     }
 }
 
 // TODO: pub struct HttpsServer<T>(pub T);
 // TODO: support web socket
 // TODO: support pipeline server
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::mock::{http_get, serve, serve_parts, serve_writes};
+    use server::{Request, Response};
+
+    fn hello(_req: Request, rsp: &mut Response) {
+        rsp.send(b"Hello World!").unwrap();
+    }
+
+    fn echo(mut req: Request, rsp: &mut Response) {
+        let mut s = String::new();
+        req.read_to_string(&mut s).unwrap();
+        rsp.send(format!("got: {}", s).as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_builder() {
+        let server = HttpServer::builder(hello)
+            .server_name("may_http")
+            .read_timeout(Some(Duration::from_secs(10)))
+            .write_timeout(Some(Duration::from_secs(5)))
+            .build();
+        assert_eq!(server.name, "may_http");
+        assert_eq!(server.read_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(server.write_timeout, Some(Duration::from_secs(5)));
+
+        let server = HttpServer::new(hello);
+        assert_eq!(server.name, "Example");
+        assert_eq!(server.read_timeout, None);
+        assert_eq!(server.write_timeout, None);
+    }
+
+    #[test]
+    fn test_strict() {
+        let bad: &[&[u8]] = &[
+            // missing host
+            b"GET / HTTP/1.1\r\n\r\n",
+            // duplicated host
+            b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+            // both content-length and transfer-encoding
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\
+              Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            // asterisk form for a non OPTIONS request
+            b"GET * HTTP/1.1\r\nHost: a\r\n\r\n",
+            // obsolete line folding
+            b"GET / HTTP/1.1\r\nHost: a\r\nX-Long: a\r\n b\r\n\r\n",
+            // bare LF line endings
+            b"GET / HTTP/1.1\nHost: a\n\n",
+        ];
+
+        let lenient = HttpServer::new(hello);
+        let strict = HttpServer::builder(hello).strict(true).build();
+        for req in bad {
+            assert!(serve(&lenient, req).starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(serve(&strict, req).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        }
+
+        let good = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert!(serve(&strict, good).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // the folding is still seen when the head ends in a later read
+        let parts: &[&[u8]] = &[b"GET / HTTP/1.1\r\nHost: a\r\nX: a\r\n b\r\n", b"\r\n"];
+        assert!(serve_parts(&lenient, parts).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(serve_parts(&strict, parts).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_on_shutdown() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        let server = HttpServer::builder(hello)
+            .on_shutdown(move || {
+                c.fetch_add(1, Ordering::SeqCst);
+            })
+            .start("127.0.0.1:0")
+            .unwrap();
+        server.shutdown();
+        server.shutdown();
+        server.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        fn panic_handler(_req: Request, _rsp: &mut Response) {
+            panic!("boom");
+        }
+
+        let server = HttpServer::new(panic_handler);
+        let input = b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(rsp.contains("connection: close\r\n"));
+        // the connection is closed after the panic
+        assert_eq!(rsp.matches("HTTP/1.1 500").count(), 1);
+
+        // the unread body is not drained while unwinding
+        let input = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert_eq!(rsp.matches("HTTP/1.1 500").count(), 1);
+    }
+
+    #[test]
+    fn test_h2_preface() {
+        let server = HttpServer::new(hello);
+        let rsp = serve(
+            &server,
+            b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0",
+        );
+        let settings = "\0\0\0\x04\0\0\0\0\0";
+        let goaway = "\0\0\x08\x07\0\0\0\0\0\0\0\0\0\0\0\0\x0d";
+        assert_eq!(rsp, format!("{}{}", settings, goaway));
+
+        // the PRI method over HTTP/1.1 is just an unknown method
+        let rsp = serve(&server, b"PRI * HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let server = HttpServer::new(echo);
+
+        // the expectation is ignored for HTTP/1.0
+        let input = b"POST / HTTP/1.0\r\nExpect: 100-continue\r\n\
+                      Content-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!rsp.contains("100 Continue"));
+        assert!(rsp.contains("got: hello"));
+
+        let input = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\
+                      Content-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("got: hello"));
+
+        let input = b"POST / HTTP/1.1\r\nExpect: magic\r\nContent-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    }
+
+    #[test]
+    fn test_expect_continue_disabled() {
+        let mut server = HttpServer::new(echo);
+        server.set_expect_continue(false);
+
+        let input = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\
+                      Content-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(!rsp.contains("100 Continue"));
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("got: hello"));
+
+        // the unknown expectation is not rejected either
+        let input = b"POST / HTTP/1.1\r\nExpect: magic\r\nContent-Length: 5\r\n\r\nhello";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("got: hello"));
+    }
+
+    #[test]
+    fn test_expect_continue_deferred() {
+        fn read_twice(mut req: Request, rsp: &mut Response) {
+            let mut buf = [0; 2];
+            req.read_exact(&mut buf).unwrap();
+            let mut s = String::from_utf8(buf.to_vec()).unwrap();
+            req.read_to_string(&mut s).unwrap();
+            rsp.send(format!("got: {}", s).as_bytes()).unwrap();
+        }
+
+        // the pipelined request also has a body for the second handler call
+        let input = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\
+                      Content-Length: 5\r\n\r\nhello\
+                      POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nok";
+
+        // sent exactly once at the first read
+        let server = HttpServer::new(read_twice);
+        let rsp = serve(&server, input);
+        assert_eq!(rsp.matches("100 Continue").count(), 1);
+        assert!(rsp.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("got: hello"));
+        assert!(rsp.contains("got: ok"));
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+
+        // also sent when the body reader is used directly
+        fn read_body(mut req: Request, rsp: &mut Response) {
+            let mut s = String::new();
+            req.body_mut().read_to_string(&mut s).unwrap();
+            rsp.send(format!("got: {}", s).as_bytes()).unwrap();
+        }
+        let server = HttpServer::new(read_body);
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("got: hello"));
+
+        // never sent if the body is not read, and the connection is closed
+        let server = HttpServer::new(hello);
+        let rsp = serve(&server, input);
+        assert!(!rsp.contains("100 Continue"));
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+    }
+
+    #[test]
+    fn test_body_drained() {
+        fn read_some(mut req: Request, rsp: &mut Response) {
+            if req.body_consumed() {
+                return rsp.send(b"empty").unwrap();
+            }
+            let mut buf = [0; 2];
+            req.read_exact(&mut buf).unwrap();
+            assert!(!req.body_consumed());
+            rsp.send(&buf).unwrap();
+        }
+
+        // the small unread body is drained and the connection is reused
+        let server = HttpServer::new(read_some);
+        let input = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                      GET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(rsp.contains("\r\n\r\nhe"));
+
+        // the size of the chunked body is unknown, the connection is closed
+        let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                      3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n\
+                      GET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+        assert!(rsp.contains("\r\n\r\nab"));
+
+        // too much left to drain, the connection is closed
+        let mut input = b"POST / HTTP/1.1\r\nContent-Length: 100000\r\n\r\n".to_vec();
+        input.extend_from_slice(&[b'a'; 100_000]);
+        input.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let rsp = serve(&server, &input);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+    }
+
+    #[test]
+    fn test_reject_before_body() {
+        fn upload(req: Request, rsp: &mut Response) {
+            if req.body_size_hint().is_none_or(|n| n > 1024) {
+                rsp.reject(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+        }
+
+        let server = HttpServer::new(upload);
+        let input = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\
+                      Content-Length: 10000000\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(!rsp.contains("100 Continue"));
+        assert!(rsp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(rsp.contains("connection: close\r\n"));
+
+        // the body is left unread and the connection is closed
+        let input = b"POST / HTTP/1.1\r\nContent-Length: 2000\r\n\r\n\
+                      GET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(!rsp.contains("200 OK"));
+    }
+
+    #[test]
+    fn test_http10_chunked_downgrade() {
+        use http::header::CONNECTION;
+
+        fn proxy(req: Request, rsp: &mut Response) {
+            // the headers copied from a chunked upstream response
+            rsp.headers_mut()
+                .insert(TRANSFER_ENCODING, "chunked".parse().unwrap());
+            rsp.headers_mut()
+                .insert(CONNECTION, "keep-alive".parse().unwrap());
+            if req.uri().path() == "/large" {
+                rsp.write_all(&vec![b'x'; 100 * 1024]).unwrap();
+            } else {
+                rsp.write_all(b"upstream ").unwrap();
+                rsp.write_all(b"body").unwrap();
+            }
+        }
+
+        let server = HttpServer::new(proxy);
+        let input = b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                      GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(!rsp.contains("transfer-encoding"));
+        assert_eq!(rsp.matches("Content-Length: 13\r\n").count(), 2);
+        assert_eq!(rsp.matches("\r\n\r\nupstream body").count(), 2);
+
+        // too large to buffer, the body ends when the connection is closed
+        let input = b"GET /large HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                      GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(!rsp.contains("transfer-encoding"));
+        assert!(!rsp.contains("Content-Length"));
+        assert!(rsp.contains("connection: close\r\n"));
+        assert!(!rsp.contains("upstream body"));
+        assert!(rsp.ends_with(&"x".repeat(100 * 1024)));
+    }
+
+    #[test]
+    fn test_trace_max_forwards() {
+        fn trace(mut req: Request, rsp: &mut Response) {
+            if !req.decrement_max_forwards() {
+                rsp.send_trace(&req).unwrap();
+                return;
+            }
+            // this is where a proxy would forward the request
+            rsp.send(b"forwarded").unwrap();
+        }
+
+        let server = HttpServer::new(trace);
+        let input = b"TRACE /a HTTP/1.1\r\nHost: x\r\nMax-Forwards: 0\r\n\
+                      Cookie: id=1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("content-type: message/http\r\n"));
+        assert!(rsp.ends_with("\r\n\r\nTRACE /a HTTP/1.1\r\nHost: x\r\nMax-Forwards: 0\r\n\r\n"));
+
+        let input = b"TRACE /a HTTP/1.1\r\nHost: x\r\nMax-Forwards: 1\r\n\r\n";
+        assert!(serve(&server, input).ends_with("forwarded"));
+    }
+
+    #[test]
+    fn test_proxy_protocol() {
+        fn client(req: Request, rsp: &mut Response) {
+            let addr = req.remote_addr().map(|a| a.to_string());
+            rsp.send(addr.unwrap_or_default().as_bytes()).unwrap();
+        }
+
+        let server = HttpServer::builder(client).proxy_protocol(true).build();
+        let input = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\n\
+                      GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert_eq!(rsp.matches("\r\n\r\n203.0.113.7:51234").count(), 2);
+
+        let mut input = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+        let src: ::std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: ::std::net::Ipv6Addr = "2001:db8::2".parse().unwrap();
+        input.extend_from_slice(&src.octets());
+        input.extend_from_slice(&dst.octets());
+        input.extend_from_slice(&[0x1f, 0x90, 0x00, 0x50]);
+        input.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let rsp = serve(&server, &input);
+        assert!(rsp.ends_with("\r\n\r\n[2001:db8::1]:8080"));
+
+        // the header is required
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.is_empty());
+    }
+
+    #[test]
+    fn test_flush_mode() {
+        let input = b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+
+        let server = HttpServer::new(hello);
+        let (rsp, writes) = serve_writes(&server, input);
+        assert_eq!(rsp.matches("Hello World!").count(), 3);
+        assert_eq!(writes, 3);
+
+        let server = HttpServer::builder(hello)
+            .flush_mode(FlushMode::OnIdle)
+            .build();
+        let (rsp, writes) = serve_writes(&server, input);
+        assert_eq!(rsp.matches("Hello World!").count(), 3);
+        assert_eq!(writes, 1);
+    }
+
+    #[test]
+    fn test_informational_flush() {
+        use http::header::{HeaderMap, LINK};
+
+        fn early_hints(_req: Request, rsp: &mut Response) {
+            let mut hints = HeaderMap::new();
+            hints.insert(LINK, "</a.css>; rel=preload".parse().unwrap());
+            let status = StatusCode::from_u16(103).unwrap();
+            rsp.send_informational(status, &hints).unwrap();
+            rsp.send(b"ok").unwrap();
+        }
+
+        // the hints are not held back by the deferred flush
+        let server = HttpServer::builder(early_hints)
+            .flush_mode(FlushMode::OnIdle)
+            .build();
+        let (rsp, writes) = serve_writes(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 103 Early Hints\r\n"));
+        assert!(rsp.ends_with("\r\n\r\nok"));
+        assert_eq!(writes, 2);
+
+        // not sent to a HTTP/1.0 client
+        let rsp = serve(&server, b"GET / HTTP/1.0\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!rsp.contains("103"));
+    }
+
+    #[test]
+    fn test_pipelined_sized_body() {
+        let server = HttpServer::new(echo);
+        let input = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                      POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        let rsp = serve(&server, input);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(rsp.contains("got: hello"));
+        assert!(rsp.contains("got: abc"));
+    }
+
+    #[test]
+    fn test_pipelined_chunked_trailers() {
+        let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                      5\r\nhello\r\n0\r\nX-Checksum: abc\r\n\r\n\
+                      POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nworld";
+        let rsp = serve(&HttpServer::new(echo), input);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(rsp.contains("got: hello"));
+        assert!(rsp.contains("got: world"));
+    }
+
+    #[test]
+    fn test_max_pipelined_requests() {
+        let input = b"GET / HTTP/1.1\r\n\r\n".repeat(5);
+        let server = HttpServer::builder(hello)
+            .flush_mode(FlushMode::OnIdle)
+            .max_pipelined_requests(Some(2))
+            .build();
+        let (rsp, writes) = serve_writes(&server, &input);
+        assert_eq!(rsp.matches("Hello World!").count(), 5);
+        // flushed after the 2nd, the 4th and the last request
+        assert_eq!(writes, 3);
+    }
+
+    #[test]
+    fn test_max_concurrent_streams_per_connection() {
+        // the yields don't break the pipeline
+        let input = b"GET / HTTP/1.1\r\n\r\n".repeat(5);
+        let server = HttpServer::builder(hello)
+            .max_concurrent_streams_per_connection(Some(2))
+            .build();
+        assert_eq!(serve(&server, &input).matches("Hello World!").count(), 5);
+
+        let server = HttpServer::builder(hello)
+            .max_concurrent_streams_per_connection(Some(1))
+            .build();
+        assert_eq!(serve(&server, &input).matches("Hello World!").count(), 5);
+    }
+
+    #[test]
+    fn test_linger() {
+        let server = HttpServer::builder(hello)
+            .linger(Some(Duration::from_secs(1)))
+            .start("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_keepalive() {
+        let server = HttpServer::builder(hello)
+            .tcp_keepalive(Some(Duration::from_secs(30)))
+            .start("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_socket_buffer_size() {
+        let server = HttpServer::builder(hello)
+            .recv_buffer_size(Some(256 * 1024))
+            .send_buffer_size(Some(256 * 1024))
+            .start("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_max_keepalive_idle_connections() {
+        use may::net::TcpStream;
+
+        let server = HttpServer::builder(hello)
+            .max_keepalive_idle_connections(Some(1))
+            .build();
+        let tracker = server.idle_tracker.clone().unwrap();
+        let server = server.start("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let request = |conn: &mut TcpStream| {
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let mut buf = [0; 1024];
+            let n = conn.read(&mut buf).unwrap();
+            assert!(buf[..n].ends_with(b"Hello World!"));
+        };
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        request(&mut first);
+        // wait until the first connection is idle
+        while tracker.idle_count() == 0 {
+            coroutine::yield_now();
+        }
+        let mut second = TcpStream::connect(addr).unwrap();
+        request(&mut second);
+
+        // the first one is the oldest idle connection
+        let mut buf = [0; 16];
+        assert_eq!(first.read(&mut buf).unwrap(), 0);
+        request(&mut second);
+        server.shutdown();
+        drop(second);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_connection_limit_per_ip() {
+        use may::net::TcpStream;
+
+        let server = HttpServer::builder(hello)
+            .connection_limit_per_ip(Some(2))
+            .start("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr();
+        let mut conns = (0..3)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        // the third connection is closed without any response
+        let mut buf = Vec::new();
+        assert_eq!(conns[2].read_to_end(&mut buf).unwrap(), 0);
+        conns[0].write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 64];
+        let n = conns[0].read(&mut buf).unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // a slot is available again after a connection is closed
+        conns.remove(0);
+        coroutine::sleep(Duration::from_millis(100));
+        assert_eq!(http_get(addr), "Hello World!");
+        drop(conns);
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_graceful_timeout() {
+        use may::net::TcpStream;
+        use std::time::Instant;
+
+        fn slow(_req: Request, rsp: &mut Response) {
+            coroutine::sleep(Duration::from_secs(2));
+            rsp.send(b"too late").ok();
+        }
+
+        let server = HttpServer::builder(slow)
+            .graceful_timeout(Some(Duration::from_millis(100)))
+            .start("127.0.0.1:0")
+            .unwrap();
+        let mut conn = TcpStream::connect(server.local_addr()).unwrap();
+        conn.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        coroutine::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        server.shutdown();
+        server.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // the connection is closed without the response
+        let mut rsp = Vec::new();
+        conn.read_to_end(&mut rsp).ok();
+        assert!(rsp.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shutdown_on_signal() {
+        use std::os::raw::c_int;
+
+        extern "C" {
+            fn raise(sig: c_int) -> c_int;
+        }
+
+        let server = HttpServer::builder(hello)
+            .shutdown_on_signal(Signal::Term)
+            .start("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        // SIGTERM, the handler is installed so the process is not killed
+        assert_eq!(unsafe { raise(15) }, 0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_content_length() {
+        fn echo(mut req: Request, rsp: &mut Response) {
+            let mut body = Vec::new();
+            req.read_to_end(&mut body).unwrap();
+            rsp.send(&body).unwrap();
+        }
+
+        let server = HttpServer::new(echo);
+        for value in &["12abc", "+5", "0x10", "-3", "18446744073709551616"] {
+            let input = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nhello", value);
+            let rsp = serve(&server, input.as_bytes());
+            assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", value);
+            assert!(!rsp.contains("hello"));
+        }
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_duplicate_transfer_encoding() {
+        let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                      Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        let rsp = serve(&HttpServer::new(hello), input);
+        assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(!rsp.contains("Hello World!"));
+    }
+
+    #[test]
+    fn test_negotiate_auth() {
+        fn negotiate(req: Request, rsp: &mut Response) {
+            match req.authorization() {
+                Some((scheme, token)) if scheme.eq_ignore_ascii_case("negotiate") => {
+                    // the application runs the SPNEGO handshake on the token
+                    let reply = format!("Negotiate {}", token.to_lowercase());
+                    rsp.set_www_authenticate(&reply).unwrap();
+                    rsp.send(b"welcome").unwrap();
+                }
+                _ => {
+                    *rsp.status_mut() = StatusCode::UNAUTHORIZED;
+                    rsp.set_www_authenticate("Negotiate").unwrap();
+                }
+            }
+        }
+
+        let server = HttpServer::new(negotiate);
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(rsp.contains("www-authenticate: Negotiate\r\n"));
+
+        let input = b"GET / HTTP/1.1\r\nAuthorization: negotiate YIIGHGYJKOZ=\r\n\r\n";
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("www-authenticate: Negotiate yiighgyjkoz=\r\n"));
+        assert!(rsp.ends_with("welcome"));
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        fn upload(mut req: Request, rsp: &mut Response) {
+            let mut body = Vec::new();
+            match req.read_to_end(&mut body) {
+                Ok(_) => rsp.send(b"complete").unwrap(),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                    *rsp.status_mut() = StatusCode::BAD_REQUEST;
+                    rsp.send(b"truncated").unwrap();
+                }
+            }
+        }
+
+        let server = HttpServer::new(upload);
+        // the client closes after 5 of the 10 bytes
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello",
+        );
+        assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(rsp.ends_with("truncated"));
+    }
+
+    #[test]
+    fn test_default_response_version() {
+        let input = b"GET /\x01 HTTP/1.1\r\n\r\n";
+        let rsp = serve(&HttpServer::new(hello), input);
+        assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let server = HttpServer::builder(hello)
+            .default_response_version(Version::HTTP_10)
+            .build();
+        let rsp = serve(&server, input);
+        assert!(rsp.starts_with("HTTP/1.0 400 Bad Request\r\n"));
+        assert!(rsp.contains("Connection: close\r\n"));
+        // the normal responses are not affected
+        let rsp = serve(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_nodelay_per_response() {
+        let server = HttpServer::builder(hello)
+            .nodelay_per_response(true)
+            .start("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        assert_eq!(http_get(server.local_addr()), "Hello World!");
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_body_inactivity_timeout() {
+        use http::header::CONNECTION;
+        use may::net::TcpStream;
+
+        fn read_body(mut req: Request, rsp: &mut Response) {
+            rsp.headers_mut()
+                .insert(CONNECTION, "close".parse().unwrap());
+            let mut s = String::new();
+            match req.read_to_string(&mut s) {
+                Ok(_) => rsp.send(format!("got: {}", s).as_bytes()).unwrap(),
+                Err(e) => {
+                    *rsp.status_mut() = StatusCode::REQUEST_TIMEOUT;
+                    rsp.send(format!("{:?}", e.kind()).as_bytes()).unwrap();
+                }
+            }
+        }
+
+        let server = HttpServer::builder(read_body)
+            .body_inactivity_timeout(Some(Duration::from_millis(100)))
+            .start("127.0.0.1:0")
+            .unwrap();
+        let mut conn = TcpStream::connect(server.local_addr()).unwrap();
+        // the body stalls after 5 bytes
+        conn.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        let mut rsp = String::new();
+        conn.read_to_string(&mut rsp).unwrap();
+        assert!(rsp.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_listener_fd() {
+        use socket2::Socket;
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+        let server = HttpServer::new(hello).start("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        assert_eq!(http_get(addr), "Hello World!");
+
+        // duplicate the fd as a supervisor would do before the exec
+        let socket = unsafe { Socket::from_raw_fd(server.listener_fd()) };
+        let dup = socket.try_clone().unwrap();
+        // the fd is still owned by the server
+        let _ = socket.into_raw_fd();
+        server.shutdown();
+        server.join().unwrap();
+
+        let listener = unsafe { TcpListener::from_raw_fd(dup.into_raw_fd()) };
+        let server = HttpServer::new(echo).serve_listener(listener).unwrap();
+        assert_eq!(server.local_addr(), addr);
+        assert_eq!(http_get(addr), "got: ");
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_response_buffer_high_watermark() {
+        fn stream(_req: Request, rsp: &mut Response) {
+            rsp.set_content_length(8000);
+            for _ in 0..80 {
+                rsp.write_all(&[b'a'; 100]).unwrap();
+            }
+        }
+
+        let input = b"GET / HTTP/1.1\r\n\r\n";
+        let (rsp, default_writes) = serve_writes(&HttpServer::new(stream), input);
+        assert!(rsp.ends_with(&"a".repeat(8000)));
+        // never more than 1000 bytes are buffered
+        let server = HttpServer::builder(stream)
+            .response_buffer_high_watermark(1000)
+            .build();
+        let (rsp, writes) = serve_writes(&server, input);
+        assert!(rsp.ends_with(&"a".repeat(8000)));
+        assert!(writes >= 8);
+        assert!(writes > default_writes);
+    }
+
+    #[test]
+    fn test_secure_cookie_policy() {
+        use http::header::SET_COOKIE;
+
+        fn login(_req: Request, rsp: &mut Response) {
+            let headers = rsp.headers_mut();
+            headers.append(SET_COOKIE, "sid=1; Secure; HttpOnly".parse().unwrap());
+            headers.append(SET_COOKIE, "theme=dark".parse().unwrap());
+        }
+
+        let req = b"GET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&HttpServer::new(login), req);
+        assert!(rsp.contains("set-cookie: sid=1; Secure; HttpOnly\r\n"));
+
+        let server = HttpServer::builder(login)
+            .secure_cookie_policy(SecureCookiePolicy::Strip)
+            .build();
+        let rsp = serve(&server, req);
+        assert!(rsp.contains("set-cookie: sid=1; HttpOnly\r\n"));
+        assert!(rsp.contains("set-cookie: theme=dark\r\n"));
+
+        let server = HttpServer::builder(login)
+            .secure_cookie_policy(SecureCookiePolicy::Reject)
+            .trust_proxy(true)
+            .build();
+        let rsp = serve(&server, req);
+        assert!(!rsp.contains("sid=1"));
+        assert!(rsp.contains("set-cookie: theme=dark\r\n"));
+        // kept on a https connection
+        let req = b"GET / HTTP/1.1\r\nX-Forwarded-Proto: https\r\n\r\n";
+        assert!(serve(&server, req).contains("set-cookie: sid=1; Secure; HttpOnly\r\n"));
+    }
+
+    #[test]
+    fn test_vectored_io() {
+        fn large(_req: Request, rsp: &mut Response) {
+            let body = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            rsp.send(&body).unwrap();
+        }
+
+        let input = b"GET / HTTP/1.1\r\n\r\n".repeat(10);
+        let (plain, plain_writes) = serve_writes(&HttpServer::new(large), &input);
+        let server = HttpServer::builder(large).vectored_io(true).build();
+        let (rsp, writes) = serve_writes(&server, &input);
+        assert_eq!(rsp, plain);
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 10);
+        assert!(writes < plain_writes);
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        fn read_body(mut req: Request, rsp: &mut Response) {
+            let mut s = String::new();
+            match req.read_to_string(&mut s) {
+                Ok(_) => rsp.send(format!("got: {}", s).as_bytes()).unwrap(),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    *rsp.status_mut() = StatusCode::BAD_REQUEST;
+                }
+            }
+        }
+
+        let server = HttpServer::builder(read_body).max_line_length(8).build();
+        let head = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let mut input = head.to_vec();
+        input.extend_from_slice(b"3;a=b\r\nabc\r\n0\r\n\r\n");
+        assert!(serve(&server, &input).contains("got: abc"));
+        let mut input = head.to_vec();
+        input.extend_from_slice(b"3;name=value\r\nabc\r\n0\r\n\r\n");
+        assert!(serve(&server, &input).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        fn read_body(mut req: Request, rsp: &mut Response) {
+            let mut s = String::new();
+            match req.read_to_string(&mut s) {
+                Ok(_) => rsp.send(format!("got: {}", s).as_bytes()).unwrap(),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    *rsp.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                }
+            }
+        }
+
+        let server = HttpServer::builder(read_body)
+            .max_body_size(Some(5))
+            .build();
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(rsp.contains("got: hello"));
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!",
+        );
+        assert!(rsp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n";
+        let rsp = serve(&server, chunked);
+        assert!(rsp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        // the body reader is limited too, and the rest is not drained
+        fn read_raw(mut req: Request, rsp: &mut Response) {
+            let mut s = String::new();
+            let ret = req.body_mut().read_to_string(&mut s);
+            assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            *rsp.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+        }
+        let server = HttpServer::builder(read_raw).max_body_size(Some(5)).build();
+        let rsp = serve(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!\
+              GET / HTTP/1.1\r\n\r\n",
+        );
+        assert!(rsp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert_eq!(rsp.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    fn test_take_raw_stream() {
+        fn raw(_req: Request, rsp: &mut Response) {
+            let mut stream = rsp.take_raw_stream().unwrap();
+            stream.write_all(b"raw bytes").unwrap();
+            stream.flush().unwrap();
+            assert!(rsp.take_raw_stream().is_err());
+            // the body is discarded
+            rsp.write_all(b"ignored").unwrap();
+        }
+
+        let input = b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let rsp = serve(&HttpServer::new(raw), input);
+        assert!(rsp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rsp.contains("connection: close\r\n"));
+        assert!(!rsp.contains("Content-Length"));
+        assert!(!rsp.contains("transfer-encoding"));
+        // the keep-alive is skipped
+        assert!(rsp.ends_with("\r\n\r\nraw bytes"));
+        assert_eq!(rsp.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn test_default_content_type() {
+        use http::header::CONTENT_TYPE;
+
+        fn typed(_req: Request, rsp: &mut Response) {
+            rsp.headers_mut()
+                .insert(CONTENT_TYPE, "image/png".parse().unwrap());
+            rsp.send(b"png").unwrap();
+        }
+
+        fn empty(_req: Request, _rsp: &mut Response) {}
+
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let server = HttpServer::builder(hello)
+            .default_content_type(Some("application/octet-stream"))
+            .build();
+        let rsp = serve(&server, req);
+        assert!(rsp.contains("content-type: application/octet-stream\r\n"));
+        assert!(rsp.ends_with("Hello World!"));
+
+        let server = HttpServer::builder(typed)
+            .default_content_type(Some("application/octet-stream"))
+            .build();
+        let rsp = serve(&server, req);
+        assert!(rsp.contains("content-type: image/png\r\n"));
+        assert_eq!(rsp.matches("content-type").count(), 1);
+
+        let server = HttpServer::builder(empty)
+            .default_content_type(Some("application/octet-stream"))
+            .build();
+        assert!(!serve(&server, req).contains("content-type"));
+
+        // the charset is appended to a text type
+        let server = HttpServer::builder(hello)
+            .default_content_type(Some("text/plain"))
+            .default_charset(Some("utf-8"))
+            .build();
+        assert!(serve(&server, req).contains("content-type: text/plain; charset=utf-8\r\n"));
+
+        let server = HttpServer::new(hello);
+        assert!(!serve(&server, req).contains("content-type"));
+    }
+
+    #[test]
+    fn test_default_charset() {
+        use http::header::CONTENT_TYPE;
+
+        fn html(_req: Request, rsp: &mut Response) {
+            rsp.headers_mut()
+                .insert(CONTENT_TYPE, "text/html".parse().unwrap());
+            rsp.send(b"<p>hi</p>").unwrap();
+        }
+
+        fn binary(_req: Request, rsp: &mut Response) {
+            rsp.headers_mut()
+                .insert(CONTENT_TYPE, "application/octet-stream".parse().unwrap());
+            rsp.send(b"\x00\x01").unwrap();
+        }
+
+        fn latin1(_req: Request, rsp: &mut Response) {
+            rsp.headers_mut()
+                .insert(CONTENT_TYPE, "text/plain; charset=latin1".parse().unwrap());
+            rsp.send(b"hi").unwrap();
+        }
+
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let server = HttpServer::builder(html)
+            .default_charset(Some("utf-8"))
+            .build();
+        assert!(serve(&server, req).contains("content-type: text/html; charset=utf-8\r\n"));
+        let server = HttpServer::new(html);
+        assert!(serve(&server, req).contains("content-type: text/html\r\n"));
+
+        let server = HttpServer::builder(binary)
+            .default_charset(Some("utf-8"))
+            .build();
+        assert!(serve(&server, req).contains("content-type: application/octet-stream\r\n"));
+        let server = HttpServer::builder(latin1)
+            .default_charset(Some("utf-8"))
+            .build();
+        assert!(serve(&server, req).contains("content-type: text/plain; charset=latin1\r\n"));
+    }
+
+    #[test]
+    fn test_error_handler() {
+        use http::header::CONTENT_TYPE;
+
+        fn not_found(_req: Request, rsp: &mut Response) {
+            *rsp.status_mut() = StatusCode::NOT_FOUND;
+        }
+
+        fn error_page(status: StatusCode, req: &Request) -> (HeaderMap, Vec<u8>) {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "text/html".parse().unwrap());
+            let body = format!("<h1>{}</h1>{}", status.as_u16(), req.uri().path());
+            (headers, body.into_bytes())
+        }
+
+        let req = b"GET /missing HTTP/1.1\r\nHost: a\r\n\r\n";
+        let server = HttpServer::new(not_found);
+        let rsp = serve(&server, req);
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!rsp.contains("<h1>"));
+
+        let server = HttpServer::builder(not_found)
+            .error_handler(error_page)
+            .build();
+        let rsp = serve(&server, req);
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(rsp.contains("content-type: text/html\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n<h1>404</h1>/missing"));
+
+        // the handler output is not replaced
+        let server = HttpServer::builder(hello).error_handler(error_page).build();
+        assert!(serve(&server, req).ends_with("Hello World!"));
+
+        // the errors generated by the server
+        let server = HttpServer::builder(hello)
+            .strict(true)
+            .error_handler(error_page)
+            .build();
+        let rsp = serve(&server, b"GET /bad HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n<h1>400</h1>/bad"));
+    }
+
+    #[test]
+    fn test_max_request_rate() {
+        let server = HttpServer::builder(hello).max_request_rate(Some(2)).build();
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = serve(&server, &req.repeat(4));
+        assert_eq!(rsp.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert_eq!(
+            rsp.matches("HTTP/1.1 503 Service Unavailable\r\n").count(),
+            1
+        );
+        assert!(rsp.contains("retry-after: 1\r\n"));
+
+        // the bucket is still empty for the next connection
+        assert!(serve(&server, req).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    fn test_accept_retry_delay() {
+        let backoff = Duration::from_millis(100);
+        let err = |kind| io::Error::new(kind, "accept");
+
+        let reset = err(io::ErrorKind::ConnectionAborted);
+        assert_eq!(
+            accept_retry_delay(&reset, backoff),
+            Some(Duration::from_millis(0))
+        );
+        let invalid = err(io::ErrorKind::InvalidInput);
+        assert_eq!(accept_retry_delay(&invalid, backoff), None);
+
+        // EMFILE
+        let emfile = io::Error::from_raw_os_error(24);
+        assert_eq!(accept_retry_delay(&emfile, backoff), Some(backoff));
+        #[cfg(unix)]
+        assert_eq!(
+            accept_retry_delay(&io::Error::from_raw_os_error(9), backoff),
+            None
+        );
+
+        let server = HttpServer::builder(hello)
+            .accept_error_backoff(Duration::from_millis(10))
+            .build();
+        assert_eq!(server.accept_backoff, Duration::from_millis(10));
+    }
+}
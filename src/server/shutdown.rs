@@ -0,0 +1,129 @@
+//! graceful shutdown support for the http server
+//!
+use std::collections::HashMap;
+use std::io;
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use may::coroutine;
+use may::net::TcpStream;
+
+/// the shared shutdown state of a server
+pub(crate) struct Shutdown {
+    // set when the shutdown is requested
+    requested: AtomicBool,
+    // the alive connections, with a clone of the stream to close it when
+    // the drain times out
+    conns: Mutex<HashMap<usize, Option<TcpStream>>>,
+    // the id of the next connection
+    next_id: AtomicUsize,
+    // the listener address, used to wake up the accept loop
+    addr: Mutex<Option<SocketAddr>>,
+    // invoked once when the shutdown is done
+    callback: Mutex<Option<Box<FnOnce() + Send>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            requested: AtomicBool::new(false),
+            conns: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+            addr: Mutex::new(None),
+            callback: Mutex::new(None),
+        }
+    }
+
+    #[inline]
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    pub fn set_addr(&self, addr: SocketAddr) {
+        *self.addr.lock().unwrap() = Some(addr);
+    }
+
+    pub fn set_callback(&self, f: Box<FnOnce() + Send>) {
+        *self.callback.lock().unwrap() = Some(f);
+    }
+
+    /// request the shutdown and wake up the accept loop
+    pub fn request(&self) {
+        if self.requested.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let addr = *self.addr.lock().unwrap();
+        if let Some(mut addr) = addr {
+            if addr.ip().is_unspecified() {
+                let ip: IpAddr = match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::new(127, 0, 0, 1).into(),
+                    SocketAddr::V6(_) => Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1).into(),
+                };
+                addr.set_ip(ip);
+            }
+            // the accept loop would see the flag when it gets this connection
+            TcpStream::connect(addr).ok();
+        }
+    }
+
+    /// wait until all the connections are closed
+    ///
+    /// with a timeout the connections that are still alive after it are
+    /// forcibly closed, and this returns without waiting for their handlers
+    pub fn wait_conns(&self, timeout: Option<Duration>) {
+        let start = Instant::now();
+        while !self.conns.lock().unwrap().is_empty() {
+            if timeout.is_some_and(|t| start.elapsed() >= t) {
+                self.close_conns();
+                return;
+            }
+            coroutine::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // shutdown the sockets, the blocked io of the handlers would fail
+    fn close_conns(&self) {
+        let conns = self.conns.lock().unwrap();
+        error!("shutdown timeout, force close {} connections", conns.len());
+        for conn in conns.values().flatten() {
+            conn.shutdown(net::Shutdown::Both).ok();
+        }
+    }
+
+    /// run the shutdown callback, it's only invoked once
+    pub fn finish(&self) {
+        let callback = self.callback.lock().unwrap().take();
+        if let Some(f) = callback {
+            f();
+        }
+    }
+}
+
+/// track an alive connection, it's removed when dropped
+pub(crate) struct ConnGuard {
+    shutdown: Arc<Shutdown>,
+    id: usize,
+}
+
+impl ConnGuard {
+    // the stream is only cloned if it would be closed by the drain timeout
+    pub fn new(shutdown: Arc<Shutdown>, stream: &TcpStream, timeout: bool) -> io::Result<Self> {
+        let stream = if timeout {
+            Some(stream.try_clone()?)
+        } else {
+            None
+        };
+        let id = shutdown.next_id.fetch_add(1, Ordering::Relaxed);
+        shutdown.conns.lock().unwrap().insert(id, stream);
+        Ok(ConnGuard { shutdown, id })
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.shutdown.conns.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,98 @@
+//! shutdown the server on an os signal
+//!
+//! the signal handler only counts the signals, a watcher coroutine of
+//! each server polls the counts and requests the shutdown
+use std::io;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use may::coroutine;
+use server::shutdown::Shutdown;
+
+/// the signals that can trigger a graceful shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// `SIGTERM`, sent by the service managers to stop the process
+    Term,
+    /// `SIGINT`, sent by `Ctrl-C` on the terminal
+    Int,
+}
+
+// the number of the signals received, indexed by `Signal::index`
+static RECEIVED: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+// how often the watcher checks the received signals
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+}
+
+impl Signal {
+    fn index(self) -> usize {
+        match self {
+            Signal::Term => 0,
+            Signal::Int => 1,
+        }
+    }
+
+    // the same numbers on linux and the bsds
+    fn number(self) -> c_int {
+        match self {
+            Signal::Term => 15,
+            Signal::Int => 2,
+        }
+    }
+}
+
+// only async-signal-safe operations are allowed here
+extern "C" fn on_signal(signum: c_int) {
+    for &sig in &[Signal::Term, Signal::Int] {
+        if sig.number() == signum {
+            RECEIVED[sig.index()].fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// install the handler of the signals, the default action is replaced
+fn install(signals: &[Signal]) -> io::Result<()> {
+    for &sig in signals {
+        let handler = on_signal as extern "C" fn(c_int) as usize;
+        // `SIG_ERR` is -1
+        if unsafe { signal(sig.number(), handler) } == !0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// request the shutdown once any of the signals is received
+///
+/// the watcher exits when the shutdown is requested by any means
+pub(crate) fn watch(signals: &[Signal], shutdown: Arc<Shutdown>) -> io::Result<()> {
+    if signals.is_empty() {
+        return Ok(());
+    }
+    install(signals)?;
+    // only the signals received after this count
+    let signals = signals
+        .iter()
+        .map(|s| (s.index(), RECEIVED[s.index()].load(Ordering::SeqCst)))
+        .collect::<Vec<_>>();
+    go!(move || {
+        while !shutdown.is_requested() {
+            let received = signals
+                .iter()
+                .any(|&(i, base)| RECEIVED[i].load(Ordering::SeqCst) != base);
+            if received {
+                info!("shutdown signal received");
+                shutdown.request();
+                break;
+            }
+            coroutine::sleep(POLL_INTERVAL);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! socket options for the accepted connections
+//!
+//! std and `may` don't expose all the options, so they are set via `socket2`
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use may::net::TcpStream;
+use socket2::Socket;
+
+/// the socket options applied to every accepted connection
+///
+/// `None` means keep the OS default
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SocketOptions {
+    pub linger: Option<Duration>,
+    pub keepalive: Option<Duration>,
+    pub recv_buffer: Option<usize>,
+    pub send_buffer: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if let Some(linger) = self.linger {
+            with_socket(stream, |s| s.set_linger(Some(linger)))?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            // `socket2` sets the idle time with the platform specific option
+            with_socket(stream, |s| s.set_keepalive(Some(keepalive)))?;
+        }
+        if let Some(size) = self.recv_buffer {
+            with_socket(stream, |s| s.set_recv_buffer_size(size))?;
+        }
+        if let Some(size) = self.send_buffer {
+            with_socket(stream, |s| s.set_send_buffer_size(size))?;
+        }
+        Ok(())
+    }
+}
+
+/// the streams that the read timeout can be changed while serving
+pub(crate) trait SetReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl SetReadTimeout for TcpStream {
+    #[inline]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl SetReadTimeout for CorkStream {
+    #[inline]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+/// a stream that corks the socket around the response writes
+///
+/// the socket is corked on the first write so the small writes are
+/// batched, and uncorked on flush so the pending data is pushed out at
+/// once. this is `TCP_CORK` on linux, elsewhere it's done by toggling
+/// `TCP_NODELAY`. either way it costs two `setsockopt` calls per flush
+pub(crate) struct CorkStream {
+    inner: TcpStream,
+    corked: bool,
+}
+
+impl CorkStream {
+    pub fn new(inner: TcpStream) -> Self {
+        CorkStream {
+            inner,
+            corked: false,
+        }
+    }
+}
+
+impl Read for CorkStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for CorkStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.corked {
+            set_cork(&self.inner, true)?;
+            self.corked = true;
+        }
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        if self.corked {
+            set_cork(&self.inner, false)?;
+            self.corked = false;
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_cork(stream: &TcpStream, cork: bool) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    let val = cork as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_CORK,
+            &val as *const _ as *const libc::c_void,
+            mem::size_of_val(&val) as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// no `TCP_CORK` here, Nagle's algorithm batches the writes instead
+#[cfg(not(target_os = "linux"))]
+fn set_cork(stream: &TcpStream, cork: bool) -> io::Result<()> {
+    with_socket(stream, |s| s.set_nodelay(!cork))
+}
+
+// borrow the stream as a `socket2::Socket` without taking the ownership
+#[cfg(unix)]
+fn with_socket<R, F>(stream: &TcpStream, f: F) -> io::Result<R>
+where
+    F: FnOnce(&Socket) -> io::Result<R>,
+{
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+    let socket = unsafe { Socket::from_raw_fd(stream.as_raw_fd()) };
+    let ret = f(&socket);
+    // the fd is still owned by the stream, don't close it
+    let _ = socket.into_raw_fd();
+    ret
+}
+
+// borrow the stream as a `socket2::Socket` without taking the ownership
+#[cfg(windows)]
+fn with_socket<R, F>(stream: &TcpStream, f: F) -> io::Result<R>
+where
+    F: FnOnce(&Socket) -> io::Result<R>,
+{
+    use std::os::windows::io::{AsRawSocket, FromRawSocket, IntoRawSocket};
+    let socket = unsafe { Socket::from_raw_socket(stream.as_raw_socket()) };
+    let ret = f(&socket);
+    // the socket is still owned by the stream, don't close it
+    let _ = socket.into_raw_socket();
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use may::net::TcpListener;
+
+    fn connect() -> (TcpListener, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener, stream)
+    }
+
+    #[test]
+    fn test_linger() {
+        let (_listener, stream) = connect();
+        let opts = SocketOptions {
+            linger: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        opts.apply(&stream).unwrap();
+        let linger = with_socket(&stream, |s| s.linger()).unwrap();
+        assert_eq!(linger, Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let (_listener, stream) = connect();
+        let opts = SocketOptions {
+            keepalive: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        opts.apply(&stream).unwrap();
+        let keepalive = with_socket(&stream, |s| s.keepalive()).unwrap();
+        assert_eq!(keepalive, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        let (_listener, stream) = connect();
+        let opts = SocketOptions {
+            recv_buffer: Some(64 * 1024),
+            send_buffer: Some(64 * 1024),
+            ..Default::default()
+        };
+        opts.apply(&stream).unwrap();
+        // linux doubles the value for the bookkeeping overhead
+        assert!(with_socket(&stream, |s| s.recv_buffer_size()).unwrap() >= 64 * 1024);
+        assert!(with_socket(&stream, |s| s.send_buffer_size()).unwrap() >= 64 * 1024);
+    }
+
+    #[test]
+    fn test_cork_stream() {
+        let (_listener, stream) = connect();
+        let mut stream = CorkStream::new(stream);
+        stream.write_all(b"hello").unwrap();
+        assert!(is_corked(&stream.inner));
+        stream.flush().unwrap();
+        assert!(!is_corked(&stream.inner));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_corked(stream: &TcpStream) -> bool {
+        use std::mem;
+        use std::os::unix::io::AsRawFd;
+        let mut val: libc::c_int = 0;
+        let mut len = mem::size_of_val(&val) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_CORK,
+                &mut val as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        val != 0
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_corked(stream: &TcpStream) -> bool {
+        !with_socket(stream, |s| s.nodelay()).unwrap()
+    }
+}
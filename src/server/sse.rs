@@ -0,0 +1,132 @@
+//! Server-sent events
+//!
+//! helpers for `Response` to send a `text/event-stream` body
+use std::io::{self, Write};
+use std::time::Duration;
+
+use http::header::*;
+
+use server::Response;
+
+/// an event of the server-sent events stream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    /// the data of the event, it can have multiple lines
+    pub data: String,
+    /// the `id` field, the client sends it back as `Last-Event-ID`
+    pub id: Option<String>,
+    /// the `event` field, the type of the event
+    pub event: Option<String>,
+    /// the `retry` field, the reconnection time of the client
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    /// create an event with the data only
+    pub fn new<S: Into<String>>(data: S) -> Self {
+        SseEvent {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    // format the event in the wire format
+    fn encode(&self) -> io::Result<String> {
+        let mut out = String::new();
+        for (name, value) in [("id", &self.id), ("event", &self.event)].iter() {
+            if let Some(ref value) = **value {
+                if value.contains(['\r', '\n']) {
+                    let msg = format!("the sse {} contains a new line", name);
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                out.push_str(&format!("{}: {}\n", name, value));
+            }
+        }
+        if let Some(retry) = self.retry {
+            let ms = retry.as_secs() * 1000 + u64::from(retry.subsec_millis());
+            out.push_str(&format!("retry: {}\n", ms));
+        }
+        // a line ends with CRLF, CR or LF
+        let lines = self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n']));
+        for line in lines {
+            if line.is_empty() {
+                out.push_str("data:\n");
+            } else {
+                out.push_str(&format!("data: {}\n", line));
+            }
+        }
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+impl Response {
+    /// send the events as a `text/event-stream` body
+    ///
+    /// each event is flushed to the client once it's pulled from the
+    /// iterator, so a blocking iterator can wait for the next event.
+    /// when the iterator ends the body is finished, with `close` the
+    /// connection is closed after that instead of kept alive
+    pub fn event_stream<I>(&mut self, events: I, close: bool) -> io::Result<()>
+    where
+        I: IntoIterator<Item = SseEvent>,
+    {
+        {
+            let headers = self.headers_mut();
+            headers.insert(CONTENT_TYPE, "text/event-stream".parse().unwrap());
+            headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+            if close {
+                headers.insert(CONNECTION, "close".parse().unwrap());
+            }
+        }
+        for event in events {
+            self.write_all(event.encode()?.as_bytes())?;
+            self.flush_now()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_event_stream() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut rsp = Response::new(out.clone());
+            let events = vec![
+                SseEvent::new("hello"),
+                SseEvent {
+                    data: "line1\nline2".to_owned(),
+                    id: Some("2".to_owned()),
+                    event: Some("update".to_owned()),
+                    retry: Some(Duration::from_secs(3)),
+                },
+                SseEvent::new(""),
+            ];
+            rsp.event_stream(events, true).unwrap();
+        }
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.contains("content-type: text/event-stream\r\n"));
+        assert!(out.contains("connection: close\r\n"));
+        assert!(out.contains("\r\ndata: hello\n\n\r\n"));
+        assert!(
+            out.contains("\r\nid: 2\nevent: update\nretry: 3000\ndata: line1\ndata: line2\n\n\r\n")
+        );
+        assert!(out.contains("\r\ndata:\n\n\r\n"));
+        assert!(out.ends_with("\r\n0\r\n\r\n"));
+
+        let event = SseEvent::new("a\r\nb\rc\nd\n");
+        let out = event.encode().unwrap();
+        assert_eq!(out, "data: a\ndata: b\ndata: c\ndata: d\ndata:\n\n");
+
+        let mut event = SseEvent::new("x");
+        event.id = Some("a\nb".to_owned());
+        assert!(event.encode().is_err());
+    }
+}